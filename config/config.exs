@@ -59,7 +59,12 @@ config :jumpwire, :proxy,
   client_ssl: [],
   server_ssl: [],
   parse_requests: true,
-  parse_responses: true
+  parse_responses: true,
+  deny_low_confidence_scans: false
+
+config :jumpwire, :parse_cache,
+  capacity: 512,
+  max_query_bytes: 64 * 1024
 
 config :jumpwire, JumpWire.Proxy.Postgres,
   keepalive: true,
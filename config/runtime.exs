@@ -263,6 +263,18 @@ with {:ok, val} <- fetch_boolean_env("JUMPWIRE_PARSE_RESPONSES") do
   config :jumpwire, :proxy, parse_responses: val
 end
 
+with {:ok, val} <- fetch_boolean_env("JUMPWIRE_DENY_LOW_CONFIDENCE_SCANS") do
+  config :jumpwire, :proxy, deny_low_confidence_scans: val
+end
+
+with {:ok, val} <- System.fetch_env("JUMPWIRE_PARSE_CACHE_SIZE") do
+  config :jumpwire, :parse_cache, capacity: String.to_integer(val)
+end
+
+with {:ok, val} <- System.fetch_env("JUMPWIRE_PARSE_CACHE_MAX_QUERY_BYTES") do
+  config :jumpwire, :parse_cache, max_query_bytes: String.to_integer(val)
+end
+
 with {:ok, cert_dir} <- System.fetch_env("ACME_CERT_DIRECTORY") do
   config :jumpwire, :acme, cert_dir: cert_dir
 end
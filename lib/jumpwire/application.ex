@@ -149,6 +149,9 @@ defmodule JumpWire.Application do
         _ -> Application.get_env(:libcluster, :topologies)
       end
 
+    parse_cache_opts = Application.get_env(:jumpwire, :parse_cache, [])
+    :ok = JumpWire.Proxy.SQL.Parser.configure_parse_cache(parse_cache_opts)
+
     pg_proxy_opts = Application.get_env(:jumpwire, JumpWire.Proxy.Postgres)
     |> Keyword.delete(:pool_size)
 
@@ -218,7 +218,7 @@ defmodule JumpWire.Proxy.Database do
     proxy_opts = Application.get_env(:jumpwire, :proxy)
 
     flags = proxy_opts
-    |> Keyword.take([:parse_responses, :parse_requests])
+    |> Keyword.take([:parse_responses, :parse_requests, :deny_low_confidence_scans])
     |> Map.new()
 
     ssl_opts = proxy_opts[:server_ssl]
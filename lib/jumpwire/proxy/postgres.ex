@@ -32,11 +32,14 @@ defmodule JumpWire.Proxy.Postgres do
   require Postgrex.Messages
   alias JumpWire.Proxy.Postgres.Messages
   alias JumpWire.Proxy.Database
-  alias JumpWire.Proxy.SQL.Parser
+  alias JumpWire.Proxy.Request
+  alias JumpWire.Proxy.SQL.{Field, Parser}
 
   @impl Database
   def init(state) do
-    Map.put(state, :types, Postgrex.Types.new(Postgrex.DefaultTypes))
+    state
+    |> Map.put(:types, Postgrex.Types.new(Postgrex.DefaultTypes))
+    |> Map.put(:prepared_statements, Parser.prepared_registry())
   end
 
   @impl Database
@@ -105,6 +108,7 @@ defmodule JumpWire.Proxy.Postgres do
     state = state
     |> Map.update!(:metadata, fn m -> Map.put(m, :session_id, session_id) end)
     |> Map.update!(:db_socket, fn s -> Socket.set_state(s, :ready) end)
+    |> track_parameter_statuses(msg)
     |> forward_auth_ready(msg)
     |> flush_queue()
 
@@ -179,6 +183,8 @@ defmodule JumpWire.Proxy.Postgres do
     # another TCP packet
     if byte_size(rest) > query_size do
       <<query::binary-size(query_size), 0, _other::binary>> = rest
+      # a simple query destroys the unnamed prepared statement
+      Parser.close_prepared(state.prepared_statements, "")
       parse_client_query(:simple, query, data, state)
     else
       :ok = Database.socket_active(state.client_socket)
@@ -215,6 +221,43 @@ defmodule JumpWire.Proxy.Postgres do
     end
   end
 
+  @impl Database
+  def client_recv(data = <<?B, len::integer-32, rest::binary>>, state) do
+    # Bind (extended protocol)
+    state = set_query_start_time(state)
+
+    if byte_size(rest) >= len - 4 do
+      # the portal name is followed by the name of the prepared statement
+      [_portal, rest] = :binary.split(rest, <<0>>)
+      [prepared_statement_name, _params] = :binary.split(rest, <<0>>)
+      bind_prepared_statement(prepared_statement_name, data, state)
+    else
+      :ok = Database.socket_active(state.client_socket)
+      {:noreply, %{state | client_buffer: data}, @timeout}
+    end
+  end
+
+  @impl Database
+  def client_recv(data = <<?C, len::integer-32, rest::binary>>, state) do
+    # Close (extended protocol)
+    if byte_size(rest) >= len - 4 do
+      case rest do
+        <<?S, rest::binary>> ->
+          [prepared_statement_name, _] = :binary.split(rest, <<0>>)
+          Parser.close_prepared(state.prepared_statements, prepared_statement_name)
+
+        _ -> :ok
+      end
+
+      :ok = Database.msg_send(state.db_socket, data)
+      :ok = Database.socket_active(state.client_socket)
+      {:noreply, state}
+    else
+      :ok = Database.socket_active(state.client_socket)
+      {:noreply, %{state | client_buffer: data}, @timeout}
+    end
+  end
+
   @impl Database
   def client_recv(data, state = %{db_socket: %Socket{state: :ready}}) do
     # handle data coming from a client
@@ -340,13 +383,18 @@ defmodule JumpWire.Proxy.Postgres do
   end
 
   defp parse_client_query(query_info, query, data, state = %{flags: %{parse_requests: true}}) do
-    with {:ok, statements} <- Parser.parse_postgresql(query),
+    with {:ok, statements} <- parse_query(query_info, query, state),
          {:ok, requests} <- query_statements_to_requests(statements) do
       handle_client_query(requests, query_info, state)
     else
       err ->
         Logger.warn("Unable to parse PostgreSQL statement: #{inspect err}")
-        :ok = Database.msg_send(state.db_socket, data)
+
+        case apply_scanned_policies(query, state) do
+          :ok -> :ok = Database.msg_send(state.db_socket, data)
+          {:error, msgs} -> :ok = Database.msg_send(state.client_socket, msgs)
+        end
+
         :ok = Database.socket_active(state.client_socket)
         {:noreply, state}
     end
@@ -357,6 +405,118 @@ defmodule JumpWire.Proxy.Postgres do
     {:noreply, state}
   end
 
+  # Policies are checked again each time a prepared statement is bound, since they
+  # may have changed after the statement was parsed. Statements that weren't
+  # registered, such as ones that failed to parse, are passed through as-is.
+  defp bind_prepared_statement(name, data, state = %{flags: %{parse_requests: true}}) do
+    result =
+      with {:ok, statements, _types, _params} <- Parser.fetch_prepared(state.prepared_statements, name),
+           {:ok, requests} <- query_statements_to_requests(statements) do
+        Enum.reduce_while(requests, :ok, fn req, :ok ->
+          case apply_request_policies(req, state) do
+            {:ok, _request, _ref} -> {:cont, :ok}
+            err -> {:halt, err}
+          end
+        end)
+      end
+
+    case result do
+      {:error, err} when is_list(err) ->
+        # The Bind is normally sent in the same packet as its Execute and Sync, so all
+        # of them are dropped along with it. The error ends with a ReadyForQuery in
+        # place of the dropped Sync's reply, and the server never sees the portal.
+        :ok = Database.msg_send(state.client_socket, err)
+
+      _ ->
+        :ok = Database.msg_send(state.db_socket, data)
+    end
+
+    :ok = Database.socket_active(state.client_socket)
+    {:noreply, state}
+  end
+  defp bind_prepared_statement(_name, data, state) do
+    :ok = Database.msg_send(state.db_socket, data)
+    :ok = Database.socket_active(state.client_socket)
+    {:noreply, state}
+  end
+
+  # Statements from the extended protocol are kept in the connection's registry
+  # so that later Bind/Execute messages can reuse the parsed statement.
+  defp parse_query({:parse, name, params}, query, state = %{prepared_statements: registry}) do
+    Parser.prepare_statement(registry, name, query, param_types(params), encoding: client_encoding(state))
+  end
+  defp parse_query(_query_info, query, state) do
+    Parser.parse_postgresql(query, encoding: client_encoding(state))
+  end
+
+  # Statements that can't be parsed are scanned for the relations they reference,
+  # and every column of those relations is checked against the policies as if it were
+  # read. Writes are checked as well when the statement starts with INSERT, UPDATE or
+  # DELETE. A scan with low confidence may have missed relations, so it is only let
+  # through when the `deny_low_confidence_scans` proxy option isn't set.
+  defp apply_scanned_policies(query, state) do
+    deny_low_confidence = Map.get(state.flags, :deny_low_confidence_scans, false)
+
+    case Parser.scan_postgresql(query, client_encoding(state)) do
+      {:ok, %{relations: relations, confidence: confidence}} ->
+        tables = Enum.map(relations, fn
+          %{schema: nil, name: name} -> name
+          %{schema: schema, name: name} -> "#{schema}.#{name}"
+        end)
+        Logger.warn("Unparsed PostgreSQL statement references #{inspect tables} with #{confidence} confidence")
+
+        if confidence == :low and deny_low_confidence do
+          {:error, [Messages.policy_blocked_error(), Messages.ready_for_query()]}
+        else
+          query
+          |> scanned_request(relations)
+          |> apply_request_policies(state)
+          |> case do
+            {:ok, _request, _data} -> :ok
+            err -> err
+          end
+        end
+
+      err ->
+        Logger.warn("Unable to scan PostgreSQL statement: #{inspect err}")
+        if deny_low_confidence do
+          {:error, [Messages.policy_blocked_error(), Messages.ready_for_query()]}
+        else
+          :ok
+        end
+    end
+  end
+
+  defp scanned_request(query, relations) do
+    fields = Enum.map(relations, fn %{schema: schema, name: name} ->
+      %Field{column: :wildcard, table: name, schema: schema}
+    end)
+
+    write_type =
+      case Regex.run(~r/^\s*(insert|update|delete)\b/i, query, capture: :all_but_first) do
+        [type] -> type |> String.downcase() |> String.to_existing_atom()
+        _ -> nil
+      end
+
+    request = %Request{select: fields}
+    if write_type, do: Map.put(request, write_type, fields), else: request
+  end
+
+  # The encoding reported by the server is preferred, since it follows changes made
+  # with `SET client_encoding` or `set_config` after the startup message was sent.
+  defp client_encoding(%{client_encoding: encoding}) when is_binary(encoding), do: encoding
+  defp client_encoding(%{startup_params: params}) when is_map(params) do
+    Map.get(params, "client_encoding", "UTF8")
+  end
+  defp client_encoding(_state), do: "UTF8"
+
+  defp param_types(<<count::integer-16, rest::binary>>) when byte_size(rest) >= count * 4 do
+    size = count * 4
+    <<types::binary-size(size), _::binary>> = rest
+    for <<oid::integer-32 <- types>>, do: oid
+  end
+  defp param_types(_), do: []
+
   @spec handle_client_query(
     [JumpWire.Proxy.Request.t()],
     :simple | {:parse, binary(), binary()},
@@ -384,7 +544,7 @@ defmodule JumpWire.Proxy.Postgres do
   defp _handle_request(request, acc, state) do
     case apply_request_policies(request, state) do
       {:ok, _request, ref} ->
-        case Parser.to_sql(ref) do
+        case Parser.splice_sql(ref) do
           {:ok, sql} -> {:cont, {:ok, [acc, sql]}}
           err -> {:halt, err}
         end
@@ -581,11 +741,35 @@ defmodule JumpWire.Proxy.Postgres do
     {msg, %{state | row_count: 0}}
   end
 
+  defp handle_message(tag = ?S, len, data, state) do
+    # ParameterStatus
+    msg = <<tag, len::integer-32, data::binary>>
+    {msg, parameter_status(data, state)}
+  end
+
   defp handle_message(tag, len, data, state) do
     msg = <<tag, len::integer-32, data::binary>>
     {msg, state}
   end
 
+  # The server sends a ParameterStatus for each reported setting when the session
+  # starts, then again whenever one changes.
+  defp parameter_status(data, state) do
+    case :binary.split(data, <<0>>, [:global]) do
+      ["client_encoding", encoding | _] -> Map.put(state, :client_encoding, encoding)
+      _ -> state
+    end
+  end
+
+  defp track_parameter_statuses(state, <<tag, len::integer-32, rest::binary>>)
+  when byte_size(rest) >= len - 4 do
+    size = len - 4
+    <<data::binary-size(size), rest::binary>> = rest
+    state = if tag == ?S, do: parameter_status(data, state), else: state
+    track_parameter_statuses(state, rest)
+  end
+  defp track_parameter_statuses(state, _data), do: state
+
   defp decode_fields(data, labels, tables, aliases) do
     decode_fields(data, labels, tables, aliases, {%{}, %{}})
   end
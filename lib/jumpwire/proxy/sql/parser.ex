@@ -64,11 +64,473 @@ defmodule JumpWire.Proxy.SQL.Parser do
     defp resolve_schema(_acc, %Field{schema: schema}), do: schema
   end
 
-  def parse_postgresql(_query), do: :erlang.nif_error(:nif_not_loaded)
+  # queries larger than this are parsed on a dirty CPU scheduler
+  @dirty_parse_bytes 64 * 1024
+
+  @doc """
+  Parse a PostgreSQL query into a list of `{statement, ref}` tuples.
+
+  Errors are returned as `{:error, {reason, details}}`, where `details` is a map with the
+  `:message` and a suggested `:sqlstate`. Syntax errors with a `:tokenizer_error` or
+  `:parser_error` reason also include the `:line`, `:column`, byte `:offset` and
+  character `:position` of the error, and the offending `:token`, when they are known.
+
+  Large queries are parsed on a dirty CPU scheduler. The following options limit
+  what will be parsed, each returning a distinct error reason when exceeded:
+
+  - `:max_bytes` - size of the query, with a reason of `:query_too_large`
+  - `:max_depth` - recursion depth of the parser, with a reason of `:recursion_limit_exceeded`.
+    Each statement, query or subquery and expression uses one level, including every
+    parenthesized expression, so `SELECT * FROM users WHERE id = ((1))` uses five
+  - `:max_tokens` - number of tokens excluding whitespace, with a reason of `:too_many_tokens`
+
+  The query is decoded from the PostgreSQL encoding named by the `:encoding` option,
+  defaulting to `"UTF8"`. `to_sql/1` encodes the statement back into the same encoding.
+  Queries that aren't valid in the encoding return an `:invalid_encoding` error.
+
+  The `~~` operators, postfix `ISNULL` and `NOTNULL`, and COPY options such as
+  `HEADER on` are accepted in addition to what the underlying parser supports.
+  Statements it has no representation for, such as LOCK TABLE and CREATE POLICY,
+  return a `:parser_error` and can only be checked with `scan_postgresql/2`.
+
+  Converting the AST into `JumpWire.Proxy.SQL.Statement` structs is skipped when the
+  `:project` option lists the parts of each statement that are needed, any of `:kind`,
+  `:tables` and `:fields`. Each statement is then returned as a map of those parts,
+  with the rest set to `nil`. `statement_term/1` returns the full struct when needed.
+  """
+  def parse_postgresql(query, opts \\ []) do
+    limits = parse_limits(opts)
+    encoding = Keyword.get(opts, :encoding, "UTF8")
+    dirty = byte_size(query) > @dirty_parse_bytes
+
+    case Keyword.get(opts, :project) do
+      nil when dirty -> parse_postgresql_limited_dirty(query, encoding, limits)
+      nil -> parse_postgresql_limited(query, encoding, limits)
+      fields when dirty -> parse_postgresql_projected_dirty(query, encoding, limits, fields)
+      fields -> parse_postgresql_projected(query, encoding, limits, fields)
+    end
+  end
+
+  defp parse_limits(opts) do
+    %{
+      max_bytes: Keyword.get(opts, :max_bytes),
+      max_depth: Keyword.get(opts, :max_depth),
+      max_tokens: Keyword.get(opts, :max_tokens),
+    }
+  end
+
+  @doc """
+  Convert the statement held by a ref into a `JumpWire.Proxy.SQL.Statement` struct,
+  including any rewrites. Mostly useful after parsing with the `:project` option.
+  """
+  def statement_term(_ref), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Return the statement held by a ref as a term that can be edited and passed back
+  to `replace_statement/2`, for rewrites written in Elixir.
+
+  The structs returned by `parse_postgresql/2` drop the names of nested enum
+  variants and can't be converted back into a statement. This term instead mirrors
+  the `sqlparser` AST exactly, with enum variants as `{variant, value}` tuples or
+  atoms and structs as plain maps.
+  """
+  def statement_ast(_ref), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Replace the statement held by a ref with one converted from a term in the form
+  returned by `statement_ast/1`. Any earlier rewrites of the statement are discarded.
+  Returns `{:error, {:invalid_statement, details}}` when the term isn't a valid AST.
+  """
+  def replace_statement(_ref, _ast), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Find the relations and columns referenced by a query using only the SQL tokenizer.
+  This is a fallback for queries that `parse_postgresql/2` can't handle, so that
+  they can still be checked against sensitive tables.
+
+  Returns a map of `:relations`, `:columns` and a `:confidence` of `:high` or `:low`.
+  Low confidence means part of the query, such as a function in a FROM clause or
+  dynamic SQL, could reference relations that weren't found.
+  """
+  def scan_postgresql(_query, _encoding \\ "UTF8"), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Split a query containing several statements into the `{start, length}` byte range
+  of each statement, without parsing them. The ranges can be passed to `binary_part/3`.
+
+  Semicolons inside strings, quoted identifiers, dollar quoted strings and comments
+  don't end a statement. Backslashes escape quotes in E-strings, and in every string
+  when the `:standard_conforming_strings` option is `false`. It defaults to `true`.
+  """
+  def split_statements(query, opts \\ []) do
+    standard_conforming_strings = Keyword.get(opts, :standard_conforming_strings, true)
+
+    if byte_size(query) > @dirty_parse_bytes do
+      split_query_dirty(query, standard_conforming_strings)
+    else
+      split_query(query, standard_conforming_strings)
+    end
+  end
+
+  @doc false
+  def split_query(_query, _standard_conforming_strings), do: :erlang.nif_error(:nif_not_loaded)
+  @doc false
+  def split_query_dirty(_query, _standard_conforming_strings), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Recognize an `INSERT INTO table (columns) VALUES ...` query where every value is a
+  literal, without building a full AST. This is much cheaper than `parse_postgresql/2`
+  for inserts with many rows.
+
+  Returns `{:ok, {ref, info}}`, where `info` has the `:schema`, `:table`, `:columns` and
+  number of `:rows`. Any other query returns `{:error, {:not_bulk_insert, details}}`
+  and should be parsed normally.
+
+  The query is decoded with the `:encoding` option, defaulting to `"UTF8"`. When the
+  `:standard_conforming_strings` option is `false`, strings containing a backslash
+  aren't recognized since the backslash is an escape. It defaults to `true`.
+  """
+  def parse_bulk_insert(query, opts \\ []) do
+    encoding = Keyword.get(opts, :encoding, "UTF8")
+    standard_conforming_strings = Keyword.get(opts, :standard_conforming_strings, true)
+    parse_bulk_insert_query(query, encoding, standard_conforming_strings)
+  end
+
+  @doc false
+  def parse_bulk_insert_query(_query, _encoding, _standard_conforming_strings), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Stream the values of a column of a bulk insert as `{row, value}` tuples, fetching
+  `chunk_size` values at a time.
+  """
+  def bulk_insert_stream(ref, column, chunk_size \\ 1000) do
+    Stream.resource(
+      fn -> 0 end,
+      fn offset ->
+        case bulk_insert_values(ref, column, offset, chunk_size) do
+          [] -> {:halt, offset}
+          values when is_list(values) -> {values, offset + length(values)}
+          err -> raise ArgumentError, "unable to read bulk insert values: #{inspect err}"
+        end
+      end,
+      fn _ -> :ok end
+    )
+  end
+
+  @doc """
+  Return up to `limit` values of a column of a bulk insert as `{row, value}` tuples,
+  starting from the row at `offset`.
+  """
+  def bulk_insert_values(_ref, _column, _offset, _limit), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Replace values of a column of a bulk insert, given as `{row, value}` tuples.
+  """
+  def replace_bulk_insert_values(_ref, _column, _replacements), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Convert a bulk insert back into SQL. Only replaced values are rewritten, the rest
+  of the query is copied unchanged.
+  """
+  def bulk_insert_to_sql(_ref), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Return counters for the cache of parsed queries as a map of `hits`, `misses`,
+  `size` and `capacity`. Queries are cached by their text, dialect and client
+  encoding, so repeated queries skip tokenizing and parsing entirely.
+  """
+  def parse_cache_stats(), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Remove every query from the parse cache. The hit and miss counters are kept.
+  """
+  def clear_parse_cache(), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Configure the parse cache with the following options:
+
+  - `:capacity` - number of distinct queries kept, defaulting to 512. Lowering it evicts
+    the least recently used queries, and 0 disables the cache.
+  - `:max_query_bytes` - size of the largest query that is cached, defaulting to 64KB,
+    so that large one-off queries don't evict frequently used ones.
+  """
+  def configure_parse_cache(opts) do
+    capacity = Keyword.get(opts, :capacity, 512)
+    max_query_bytes = Keyword.get(opts, :max_query_bytes, 64 * 1024)
+    configure_parse_cache(capacity, max_query_bytes)
+  end
+
+  @doc false
+  def configure_parse_cache(_capacity, _max_query_bytes), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Emit telemetry for the parse cache. Called periodically by `JumpWire.Telemetry`.
+  """
+  def measure_parse_cache() do
+    stats = parse_cache_stats()
+    :telemetry.execute([:sql, :parse_cache], Map.take(stats, [:hits, :misses, :size]), %{node: node()})
+  end
+
+  @doc false
+  def parse_postgresql_limited(_query, _encoding, _limits), do: :erlang.nif_error(:nif_not_loaded)
+  @doc false
+  def parse_postgresql_limited_dirty(_query, _encoding, _limits), do: :erlang.nif_error(:nif_not_loaded)
+  @doc false
+  def put_sql_comment_tags(_ref, _tags), do: :erlang.nif_error(:nif_not_loaded)
+  @doc false
+  def parse_postgresql_projected(_query, _encoding, _limits, _fields), do: :erlang.nif_error(:nif_not_loaded)
+  @doc false
+  def parse_postgresql_projected_dirty(_query, _encoding, _limits, _fields), do: :erlang.nif_error(:nif_not_loaded)
+
   def debug_parse(_query, _dialect), do: :erlang.nif_error(:nif_not_loaded)
   def to_sql(_query), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Convert a statement back into SQL like `to_sql/1`, but only the parts changed by
+  rewrites are rendered. Everything else is copied from the original query byte for
+  byte, keeping comments such as sqlcommenter tags, whitespace and the spelling of
+  literals intact. The whole statement is rendered when the changes can't be lined
+  up with the original text.
+  """
+  def splice_sql(_ref), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Return the tags of every sqlcommenter comment in a query as a map, such as
+  `%{"application" => "x", "route" => "/users"}` for
+  `/*application='x',route='%2Fusers'*/`. Other comments are ignored.
+  """
+  def sql_comment_tags(_query, _encoding \\ "UTF8"), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Append tags to a statement as a sqlcommenter comment when it is converted back
+  into SQL with `to_sql/1` or `splice_sql/1`, for correlating database logs with
+  JumpWire traces. Tags can be given as a map or keyword list, and replace any
+  tags previously added with the same keys. Tags added to a transaction from
+  `begin_rewrite/1` are added to its statement by `commit_rewrite/2`.
+
+  When the SQL already ends with a sqlcommenter comment, the tags are merged into
+  it rather than appended as a second comment. Tags are never added to
+  `COPY ... FROM STDIN`, since its data follows the statement.
+  """
+  def add_sql_comment_tags(ref, tags) do
+    tags = Map.new(tags, fn {key, value} -> {to_string(key), to_string(value)} end)
+    put_sql_comment_tags(ref, tags)
+  end
+
   def add_table_selection(_ref, _table, _left, _op, _right), do: :erlang.nif_error(:nif_not_loaded)
 
+  @doc """
+  Apply several rewrites to a statement with a single walk over it, rather than one
+  NIF call and walk per rewrite. Each op is one of:
+
+  - `{:filter, table, column, :eq, value}` - the same as `add_table_selection/5`
+  - `{:mask, table, column, value}` - select `value` in place of the column, including
+    within expressions such as `lower(ssn)`
+  - `{:limit, max}` - cap the number of rows returned, through FETCH FIRST if the query uses it
+  - `{:substitute, table, replacement}` - read from `replacement` instead of `table`
+  - `{:qualify, schema}` - qualify tables referenced without a schema
+  - `{:expand_views, catalog, tables}` - the same as `expand_views/3`
+
+  Returns `{:ok, counts}` with the number of places each op was applied, in order.
+  Ops are applied in the order given at each part of the statement, so later ops see
+  the changes made by earlier ones. An unknown op or one with the wrong arguments
+  returns `{:error, {:invalid_op, op}}` without changing the statement. A statement that
+  can't be rewritten safely, such as one selecting a masked column through `*`, returns
+  `{:error, {:unsupported_statement, msg}}` and is also left unchanged.
+  """
+  def apply_rewrites(_ref, _ops), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Point table references at other tables, given as a map or keyword list of table
+  names to their replacements. For example, `%{"users" => "users_redacted"}` reads
+  from a masked view instead of `users`. A qualified name such as `"public.users"`
+  only matches references using that same qualification. A redirected table keeps
+  its original name as an alias, so column references still resolve. The target of
+  an INSERT can't be aliased, so its RETURNING and ON CONFLICT column references are
+  qualified with the replacement instead.
+
+  With the `:schema` option, tables referenced without a schema are qualified with
+  it after being redirected, so `users` becomes `tenant_42.users`. CTE names, table
+  functions and system tables (see `system_schema/1`) are never rewritten.
+
+  Returns `{:ok, count}` with the number of references rewritten.
+  """
+  def substitute_tables(ref, tables, opts \\ []) do
+    tables = Enum.map(tables, fn {table, replacement} -> {to_string(table), to_string(replacement)} end)
+    rewrite_table_names(ref, tables, Keyword.get(opts, :schema))
+  end
+  def rewrite_table_names(_ref, _tables, _schema), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Create a catalog for storing the view definitions of a database.
+  """
+  def view_catalog(), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Parse the definition of a view and store it in the catalog under its name,
+  replacing any previous definition. The definition can either be the query of the
+  view, such as from the `definition` column of `pg_views`, or a `CREATE VIEW`
+  statement. Returns `{:error, {:invalid_view, error}}` for any other statement.
+  """
+  def load_view(_catalog, _name, _definition), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Remove a view from the catalog.
+  """
+  def remove_view(_catalog, _name), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Inline the views in the catalog that read from any of the given tables, either
+  directly or through other views, as derived tables. This lets table filters such as
+  `add_table_selection/5` apply to queries made through a view. Views keep their name
+  as an alias. A view written to by an UPDATE or DELETE is left in place, while other
+  reads of it in the same statement are still inlined. A table or view name without a
+  schema is in `public`, so `orders` matches `public.orders` but not `reports.orders`.
+
+  Returns `{:ok, count}` with the number of views inlined.
+  """
+  def expand_views(_ref, _catalog, _tables), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Start a transaction for speculative rewrites of a statement. The returned ref can
+  be passed to any rewriting function in place of the original, which is left
+  unchanged until the transaction is committed with `commit_rewrite/2`.
+
+  Values found with `find_column_values/3` or `find_predicate_values/3` must be
+  replaced through the same ref they were found with. Their version is stale for
+  every other ref, including the transaction's original statement, so finding and
+  replacing values should both happen inside the transaction.
+  """
+  def begin_rewrite(_ref), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Apply the rewrites of a transaction to the statement it was started from. Returns
+  `:conflict` if that statement was rewritten after the transaction began.
+  """
+  def commit_rewrite(_ref, _transaction), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Undo every rewrite of a transaction since it was started with `begin_rewrite/1`.
+  """
+  def rollback_rewrite(_transaction), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Find literal values that an INSERT or UPDATE statement writes to any of the given
+  columns of a table. Returns `{version, values}`, where each value is
+  `{index, column, value}` and the index can be passed to `replace_column_values/5`
+  along with the version to substitute a new value.
+  """
+  def find_column_values(_ref, _table, _columns), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Replace literal values previously found with `find_column_values/3`, such as
+  swapping plaintext for ciphertext before the statement is sent upstream. Returns
+  `:stale` without changing anything if the statement was rewritten after the values
+  were found, since the indexes may no longer point at the same values.
+  """
+  def replace_column_values(_ref, _version, _table, _columns, _replacements),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Find literal values compared against any of the given columns of a table in an
+  equality or IN predicate, such as `WHERE ssn = '123'`. Predicates are searched for
+  anywhere in the statement, including JOIN ... ON and HAVING clauses, CASE expressions
+  and subqueries. Values are returned in the same `{version, values}` shape as
+  `find_column_values/3`. Placeholders and other expressions in an IN list are skipped,
+  while the literals around them are kept.
+  """
+  def find_predicate_values(_ref, _table, _columns), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Replace literal values previously found with `find_predicate_values/3`, such as
+  swapping a plaintext value for its token so lookups on tokenized columns still match.
+  Returns `:stale` under the same conditions as `replace_column_values/5`.
+  """
+  def replace_predicate_values(_ref, _version, _table, _columns, _replacements),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Create a reference to a secret key used for computing blind indexes.
+  """
+  def blind_index_key(_key), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Rewrite a statement so that encrypted columns are searched through a blind index.
+  Indexes are given as `{column, index_column}` pairs. Equality and IN predicates on the
+  column are replaced with an HMAC of the value compared against the index column, and
+  inserts or updates of the column also write the index column.
+
+  Errors are returned as `{:error, {reason, details}}`, with details in the same shape as
+  `parse_postgresql/2`, leaving the statement unchanged:
+
+  - `:unsupported_statement` - an INSERT without a column list
+  - `:unsupported_insert_source` - an INSERT from a query instead of VALUES
+  - `:unsupported_value` - a value that isn't a literal, such as a placeholder
+
+  Placeholders are only known once bound, so parameterized statements need to write or
+  compare the index column themselves, such as
+  `INSERT INTO users (email, email_bidx) VALUES ($1, $2)` with the HMAC bound to `$2`.
+  Inserts and updates that already set the index column are left alone.
+  """
+  def add_blind_index(_ref, _key, _table, _indexes), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Find operations on encrypted or tokenized columns that the database can't perform
+  correctly, such as ORDER BY, range comparisons, LIKE, arithmetic, aggregates and joins.
+  Each violation is returned as `{kind, column, message}` with a message suitable for
+  returning to the client.
+  """
+  def validate_columns(_ref, _table, _columns), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Map each `$n` or `?` placeholder in a prepared statement to the column it is compared
+  to, inserted into or assigned to. Each placeholder is returned as a map with its
+  1-based `:index` in the Bind parameters, the `:usage` (`:compare`, `:insert`,
+  `:assign` or `:other`) and the `:schema`, `:table` and `:column` it targets, if known.
+  """
+  def parameter_targets(_ref), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Create a registry for storing the prepared statements of a single client connection.
+  """
+  def prepared_registry(), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Parse the query from a Parse message and store it in the registry under the statement
+  name, along with the declared parameter type OIDs. Takes the same `:encoding` and limit
+  options as `parse_postgresql/2` and returns the same result.
+  """
+  def prepare_statement(registry, name, query, param_types, opts \\ []) do
+    limits = parse_limits(opts)
+    encoding = Keyword.get(opts, :encoding, "UTF8")
+
+    if byte_size(query) > @dirty_parse_bytes do
+      prepare_statement_limited_dirty(registry, name, query, param_types, encoding, limits)
+    else
+      prepare_statement_limited(registry, name, query, param_types, encoding, limits)
+    end
+  end
+
+  @doc false
+  def prepare_statement_limited(_registry, _name, _query, _param_types, _encoding, _limits),
+    do: :erlang.nif_error(:nif_not_loaded)
+  @doc false
+  def prepare_statement_limited_dirty(_registry, _name, _query, _param_types, _encoding, _limits),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Fetch a prepared statement by name as `{:ok, statements, param_types, parameters}`,
+  where `parameters` is the result of `parameter_targets/1`. The statement refs are
+  new copies on every call, so rewrites from one execution don't affect later ones.
+  Unknown names return `{:error, {:not_found, details}}`.
+  """
+  def fetch_prepared(_registry, _name), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Remove a prepared statement from the registry.
+  """
+  def close_prepared(_registry, _name), do: :erlang.nif_error(:nif_not_loaded)
+
   @doc """
   In PostgreSQL, system tables names always being with `pg_`. Unqualified references will
   resolve to system tables.
@@ -95,6 +95,20 @@ defmodule JumpWire.Telemetry do
         description: "percentage of rows for a given field that are detokenized",
         tags: [:database, :table, :field, :organization]
       ),
+
+      # SQL parser metrics
+      last_value("sql.parse_cache.hits",
+        description: "number of parsed queries returned from the cache",
+        tags: [:node]
+      ),
+      last_value("sql.parse_cache.misses",
+        description: "number of queries parsed without a cache hit",
+        tags: [:node]
+      ),
+      last_value("sql.parse_cache.size",
+        description: "number of queries currently held in the parse cache",
+        tags: [:node]
+      ),
     ] ++ proxy_metrics()
   end
 
@@ -121,6 +135,7 @@ defmodule JumpWire.Telemetry do
   def measurements() do
     [
       {JumpWire.Proxy, :measure_proxies, []},
+      {JumpWire.Proxy.SQL.Parser, :measure_parse_cache, []},
     ]
   end
 
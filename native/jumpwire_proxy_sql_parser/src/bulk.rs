@@ -0,0 +1,3 @@
+mod insert;
+
+pub use self::insert::BulkInsert;
@@ -0,0 +1,359 @@
+use crate::encoding::ClientEncoding;
+use sqlparser::ast::Value;
+use sqlparser::dialect::PostgreSqlDialect;
+use sqlparser::keywords::Keyword;
+use sqlparser::tokenizer::{Token, Tokenizer};
+use std::collections::HashMap;
+use std::sync::{PoisonError, RwLock};
+
+/// A literal value in the VALUES list, along with its byte range in the query.
+struct Cell {
+    start: usize,
+    end: usize,
+    value: Value,
+}
+
+/// An `INSERT INTO t (cols) VALUES ...` statement where every value is a
+/// literal, kept as the original query text plus the position of each value
+/// instead of a full AST. This keeps bulk inserts with many thousands of rows
+/// cheap to inspect and rewrite.
+///
+/// Values are stored by column so that policies can walk the values of a
+/// single column. Replacements are kept separately and spliced into the
+/// original text when the query is turned back into SQL.
+pub struct BulkInsert {
+    sql: String,
+    encoding: ClientEncoding,
+    pub schema: Option<String>,
+    pub table: String,
+    pub columns: Vec<String>,
+    pub rows: usize,
+    cells: Vec<Vec<Cell>>,
+    replacements: RwLock<HashMap<(usize, usize), Value>>,
+}
+
+impl BulkInsert {
+    /// Recognize a bulk insert, returning None for any other statement or for
+    /// an insert using anything other than literals, such as expressions,
+    /// DEFAULT, ON CONFLICT or RETURNING. Those are left to the full parser.
+    pub fn parse(
+        sql: String,
+        encoding: ClientEncoding,
+        standard_conforming_strings: bool,
+    ) -> Option<Self> {
+        let values_start = find_values_keyword(sql.as_bytes())?;
+        let (schema, table, columns) = parse_header(&sql[..values_start])?;
+
+        let mut cells: Vec<Vec<Cell>> = columns.iter().map(|_| vec![]).collect();
+        let mut rows = 0;
+        let mut scanner = Scanner {
+            query: sql.as_bytes(),
+            position: values_start + "values".len(),
+        };
+
+        loop {
+            scanner.expect(b'(')?;
+            for (i, column) in cells.iter_mut().enumerate() {
+                if i > 0 {
+                    scanner.expect(b',')?;
+                }
+                column.push(scanner.literal(standard_conforming_strings)?);
+            }
+            scanner.expect(b')')?;
+            rows += 1;
+
+            match scanner.next_byte() {
+                Some(b',') => continue,
+                Some(b';') if scanner.next_byte().is_none() => break,
+                None => break,
+                _ => return None,
+            }
+        }
+
+        Some(BulkInsert {
+            sql,
+            encoding,
+            schema,
+            table,
+            columns,
+            rows,
+            cells,
+            replacements: RwLock::new(HashMap::new()),
+        })
+    }
+
+    pub fn encoding(&self) -> ClientEncoding {
+        self.encoding
+    }
+
+    pub fn column_index(&self, column: &str) -> Option<usize> {
+        let column = column.to_lowercase();
+        self.columns.iter().position(|c| *c == column)
+    }
+
+    /// Values of a column for a range of rows, including any replacements.
+    pub fn values(&self, column: usize, offset: usize, limit: usize) -> Vec<(usize, Value)> {
+        let replacements = self
+            .replacements
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        self.cells[column]
+            .iter()
+            .enumerate()
+            .skip(offset)
+            .take(limit)
+            .map(|(row, cell)| {
+                let value = replacements.get(&(column, row)).unwrap_or(&cell.value);
+                (row, value.clone())
+            })
+            .collect()
+    }
+
+    pub fn replace(&self, column: usize, values: Vec<(usize, Value)>) -> Result<(), &'static str> {
+        if values.iter().any(|(row, _)| *row >= self.rows) {
+            return Err("invalid_index");
+        }
+
+        let mut replacements = self
+            .replacements
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        for (row, value) in values {
+            replacements.insert((column, row), value);
+        }
+        Ok(())
+    }
+
+    /// Write the query with all replacements applied. Everything else is
+    /// copied from the original text unchanged.
+    pub fn to_sql(&self) -> String {
+        let replacements = self
+            .replacements
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        if replacements.is_empty() {
+            return self.sql.clone();
+        }
+
+        let mut sql = String::with_capacity(self.sql.len());
+        let mut last = 0;
+        for row in 0..self.rows {
+            for column in 0..self.columns.len() {
+                if let Some(value) = replacements.get(&(column, row)) {
+                    let cell = &self.cells[column][row];
+                    sql.push_str(&self.sql[last..cell.start]);
+                    sql.push_str(&value.to_string());
+                    last = cell.end;
+                }
+            }
+        }
+        sql.push_str(&self.sql[last..]);
+        sql
+    }
+}
+
+/// Find the VALUES keyword of an insert, skipping over quoted identifiers.
+fn find_values_keyword(query: &[u8]) -> Option<usize> {
+    let mut i = 0;
+    while i < query.len() {
+        match query[i] {
+            b'"' => {
+                i += 1;
+                while i < query.len() && query[i] != b'"' {
+                    i += 1;
+                }
+                i += 1;
+            }
+            // strings can only appear after VALUES
+            b'\'' | b'$' => return None,
+            c if is_identifier_byte(c) => {
+                let start = i;
+                while i < query.len() && is_identifier_byte(query[i]) {
+                    i += 1;
+                }
+                if query[start..i].eq_ignore_ascii_case(b"values") {
+                    return Some(start);
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+/// Parse `INSERT INTO [schema.]table (columns)` using the regular tokenizer.
+fn parse_header(sql: &str) -> Option<(Option<String>, String, Vec<String>)> {
+    let dialect = PostgreSqlDialect {};
+    let tokens: Vec<Token> = Tokenizer::new(&dialect, sql)
+        .tokenize()
+        .ok()?
+        .into_iter()
+        .filter(|t| !matches!(t, Token::Whitespace(_)))
+        .collect();
+
+    let mut tokens = tokens.iter();
+    match (tokens.next(), tokens.next()) {
+        (Some(Token::Word(insert)), Some(Token::Word(into)))
+            if insert.keyword == Keyword::INSERT && into.keyword == Keyword::INTO => {}
+        _ => return None,
+    }
+
+    let mut name = vec![];
+    let mut columns = vec![];
+    loop {
+        match tokens.next()? {
+            Token::Word(word) => name.push(normalize(word.value.clone(), word.quote_style)),
+            Token::Period => continue,
+            Token::LParen => break,
+            _ => return None,
+        }
+    }
+    loop {
+        match tokens.next()? {
+            Token::Word(word) => columns.push(normalize(word.value.clone(), word.quote_style)),
+            Token::Comma => continue,
+            Token::RParen => break,
+            _ => return None,
+        }
+    }
+    if tokens.next().is_some() || columns.is_empty() {
+        return None;
+    }
+
+    let table = name.pop()?;
+    let schema = name.pop();
+    if !name.is_empty() {
+        return None;
+    }
+    Some((schema, table, columns))
+}
+
+fn normalize(value: String, quote_style: Option<char>) -> String {
+    match quote_style {
+        Some(_) => value,
+        None => value.to_lowercase(),
+    }
+}
+
+fn is_identifier_byte(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_' || c >= 0x80
+}
+
+/// Scanner for the literals of a VALUES list.
+struct Scanner<'a> {
+    query: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn skip_whitespace(&mut self) {
+        loop {
+            let rest = &self.query[self.position..];
+            if rest.first().is_some_and(|c| c.is_ascii_whitespace()) {
+                self.position += 1;
+            } else if rest.starts_with(b"--") {
+                while self.position < self.query.len() && self.query[self.position] != b'\n' {
+                    self.position += 1;
+                }
+            } else {
+                return;
+            }
+        }
+    }
+
+    fn next_byte(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        let c = self.query.get(self.position).copied();
+        self.position += 1;
+        c
+    }
+
+    fn expect(&mut self, c: u8) -> Option<()> {
+        if self.next_byte()? == c {
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    /// Read a single literal value. Anything that isn't a plain string,
+    /// number, boolean, NULL or placeholder isn't supported, and neither are
+    /// strings containing a backslash unless `standard_conforming_strings`
+    /// is on, since the backslash would be an escape.
+    fn literal(&mut self, standard_conforming_strings: bool) -> Option<Cell> {
+        self.skip_whitespace();
+        let start = self.position;
+        let first = *self.query.get(start)?;
+
+        let value = match first {
+            b'\'' => {
+                let mut text = vec![];
+                let mut i = start + 1;
+                loop {
+                    match self.query.get(i)? {
+                        b'\'' if self.query.get(i + 1) == Some(&b'\'') => {
+                            text.push(b'\'');
+                            i += 2;
+                        }
+                        b'\'' => break,
+                        b'\\' if !standard_conforming_strings => return None,
+                        c => {
+                            text.push(*c);
+                            i += 1;
+                        }
+                    }
+                }
+                self.position = i + 1;
+                Value::SingleQuotedString(String::from_utf8(text).ok()?)
+            }
+            b'$' => {
+                let end = self.take_while(start + 1, |c| c.is_ascii_digit());
+                if end == start + 1 {
+                    return None;
+                }
+                Value::Placeholder(self.text(start, end)?)
+            }
+            b'-' | b'+' | b'.' | b'0'..=b'9' => {
+                let end = self.take_while(start + 1, |c| {
+                    c.is_ascii_digit() || matches!(c, b'.' | b'e' | b'E' | b'+' | b'-')
+                });
+                let text = self.text(start, end)?;
+                if !text.bytes().any(|c| c.is_ascii_digit()) {
+                    return None;
+                }
+                Value::Number(text, false)
+            }
+            _ => {
+                let end = self.take_while(start, is_identifier_byte);
+                let word = self.text(start, end)?.to_uppercase();
+                match word.as_str() {
+                    "NULL" => Value::Null,
+                    "TRUE" => Value::Boolean(true),
+                    "FALSE" => Value::Boolean(false),
+                    _ => return None,
+                }
+            }
+        };
+
+        // casts and other expressions are left to the full parser
+        let end = self.position;
+        match self.next_byte()? {
+            b',' | b')' => self.position -= 1,
+            _ => return None,
+        }
+        Some(Cell { start, end, value })
+    }
+
+    fn take_while(&mut self, from: usize, f: impl Fn(u8) -> bool) -> usize {
+        let mut end = from;
+        while end < self.query.len() && f(self.query[end]) {
+            end += 1;
+        }
+        self.position = end;
+        end
+    }
+
+    fn text(&self, start: usize, end: usize) -> Option<String> {
+        String::from_utf8(self.query[start..end].to_vec()).ok()
+    }
+}
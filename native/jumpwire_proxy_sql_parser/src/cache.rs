@@ -0,0 +1,3 @@
+mod parse;
+
+pub use self::parse::{CacheStats, ParseCache};
@@ -0,0 +1,232 @@
+use rustler::env::{OwnedEnv, SavedTerm};
+use rustler::{Env, NifMap, Term};
+use sqlparser::ast::Statement;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+
+/// Number of distinct queries kept in the cache until it is configured.
+const DEFAULT_CAPACITY: usize = 512;
+/// Queries larger than this are never cached until the cache is configured,
+/// so that one-off bulk queries don't evict the hot ones.
+const DEFAULT_MAX_QUERY_BYTES: usize = 64 * 1024;
+
+static CACHE: OnceLock<ParseCache> = OnceLock::new();
+
+/// A successfully parsed query. The statements are shared by every resource
+/// created from the entry, which is safe since resources never rewrite a
+/// statement in place.
+struct Entry {
+    sql: String,
+    dialect: &'static str,
+    encoding: &'static str,
+    statements: Vec<Arc<Statement>>,
+    tokens: usize,
+    /// The serialized statements, kept in an environment owned by the cache
+    /// and copied into the calling process on every hit.
+    term: Mutex<(OwnedEnv, SavedTerm)>,
+    last_used: AtomicU64,
+}
+
+/// A cached parse, with the statement terms copied into the calling process.
+pub struct Cached<'a> {
+    pub statements: Vec<Arc<Statement>>,
+    /// Number of tokens in the query, excluding whitespace.
+    pub tokens: usize,
+    pub term: Term<'a>,
+}
+
+#[derive(NifMap)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub size: usize,
+    pub capacity: usize,
+}
+
+/// Bounded LRU cache of parsed queries, keyed by a hash of the query text,
+/// dialect and client encoding. The full text is compared on every hit so
+/// that a hash collision can never return the wrong statements.
+pub struct ParseCache {
+    entries: Mutex<HashMap<u64, Arc<Entry>>>,
+    capacity: AtomicUsize,
+    max_query_bytes: AtomicUsize,
+    clock: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl ParseCache {
+    pub fn global() -> &'static ParseCache {
+        CACHE.get_or_init(|| ParseCache {
+            entries: Mutex::new(HashMap::new()),
+            capacity: AtomicUsize::new(DEFAULT_CAPACITY),
+            max_query_bytes: AtomicUsize::new(DEFAULT_MAX_QUERY_BYTES),
+            clock: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        })
+    }
+
+    /// Change the number of queries kept in the cache and the size of the
+    /// largest query that will be cached. Lowering the capacity evicts the
+    /// least recently used queries, and a capacity of 0 disables the cache.
+    pub fn configure(&self, capacity: usize, max_query_bytes: usize) {
+        self.capacity.store(capacity, Ordering::Relaxed);
+        self.max_query_bytes
+            .store(max_query_bytes, Ordering::Relaxed);
+
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        while entries.len() > capacity {
+            evict_oldest(&mut entries);
+        }
+    }
+
+    pub fn cacheable(&self, sql: &str) -> bool {
+        sql.len() <= self.max_query_bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn get<'a>(
+        &self,
+        env: Env<'a>,
+        sql: &str,
+        dialect: &'static str,
+        encoding: &'static str,
+    ) -> Option<Cached<'a>> {
+        let entry = self.lookup(sql, dialect, encoding)?;
+        let term = {
+            let saved = entry.term.lock().unwrap_or_else(PoisonError::into_inner);
+            let (owned_env, term) = &*saved;
+            owned_env.run(|cache_env| term.load(cache_env).in_env(env))
+        };
+        Some(Cached {
+            statements: entry.statements.clone(),
+            tokens: entry.tokens,
+            term,
+        })
+    }
+
+    /// Same as `get`, without copying the serialized statements. Returns the
+    /// statements and their token count.
+    pub fn statements(
+        &self,
+        sql: &str,
+        dialect: &'static str,
+        encoding: &'static str,
+    ) -> Option<(Vec<Arc<Statement>>, usize)> {
+        let entry = self.lookup(sql, dialect, encoding)?;
+        Some((entry.statements.clone(), entry.tokens))
+    }
+
+    fn lookup(
+        &self,
+        sql: &str,
+        dialect: &'static str,
+        encoding: &'static str,
+    ) -> Option<Arc<Entry>> {
+        let key = cache_key(sql, dialect, encoding);
+        let entry = self
+            .entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&key)
+            .cloned()
+            .filter(|e| e.sql == sql && e.dialect == dialect && e.encoding == encoding);
+
+        let entry = match entry {
+            Some(entry) => entry,
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                return None;
+            }
+        };
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        entry.last_used.store(
+            self.clock.fetch_add(1, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        Some(entry)
+    }
+
+    pub fn insert(
+        &self,
+        sql: &str,
+        dialect: &'static str,
+        encoding: &'static str,
+        statements: Vec<Arc<Statement>>,
+        tokens: usize,
+        term: Term,
+    ) {
+        let capacity = self.capacity.load(Ordering::Relaxed);
+        if capacity == 0 {
+            return;
+        }
+
+        let owned_env = OwnedEnv::new();
+        let saved = owned_env.save(term);
+        let entry = Entry {
+            sql: sql.to_string(),
+            dialect,
+            encoding,
+            statements,
+            tokens,
+            term: Mutex::new((owned_env, saved)),
+            last_used: AtomicU64::new(self.clock.fetch_add(1, Ordering::Relaxed)),
+        };
+
+        let key = cache_key(sql, dialect, encoding);
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        // the same query may have been parsed by another process since the
+        // miss, in which case its entry is replaced rather than evicting
+        // another one
+        if !entries.contains_key(&key) {
+            while entries.len() >= capacity {
+                evict_oldest(&mut entries);
+            }
+        }
+        entries.insert(key, Arc::new(entry));
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let size = self
+            .entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len();
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            size,
+            capacity: self.capacity.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn clear(&self) {
+        self.entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+}
+
+/// Remove the least recently used entry. A linear scan is cheap enough at the
+/// sizes the cache is used with, and only happens on a miss with a full cache.
+fn evict_oldest(entries: &mut HashMap<u64, Arc<Entry>>) {
+    let oldest = entries
+        .iter()
+        .min_by_key(|(_, e)| e.last_used.load(Ordering::Relaxed))
+        .map(|(key, _)| *key);
+    if let Some(oldest) = oldest {
+        entries.remove(&oldest);
+    }
+}
+
+fn cache_key(sql: &str, dialect: &str, encoding: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    dialect.hash(&mut hasher);
+    encoding.hash(&mut hasher);
+    sql.hash(&mut hasher);
+    hasher.finish()
+}
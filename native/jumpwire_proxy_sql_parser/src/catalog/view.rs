@@ -0,0 +1,94 @@
+use crate::matcher::same_table;
+use sqlparser::ast::{visit_relations, Ident, Query, Statement};
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+use std::sync::{Arc, PoisonError, RwLock};
+
+/// The query a view is defined by, along with the relations it reads from.
+pub struct View {
+    pub query: Query,
+    /// Column names given when creating the view, if any.
+    pub columns: Vec<Ident>,
+    relations: Vec<Vec<String>>,
+}
+
+impl View {
+    /// Create a view from its definition, either a query or a `CREATE VIEW`
+    /// statement. Returns `None` for any other statement.
+    pub fn from_statement(statement: Statement) -> Option<Self> {
+        let (query, columns) = match statement {
+            Statement::Query(query) => (*query, vec![]),
+            Statement::CreateView { query, columns, .. } => (*query, columns),
+            _ => return None,
+        };
+
+        let mut relations = vec![];
+        let _ = visit_relations(&query, |name| {
+            relations.push(name.0.iter().map(|i| i.value.to_lowercase()).collect());
+            ControlFlow::<()>::Continue(())
+        });
+
+        Some(View {
+            query,
+            columns,
+            relations,
+        })
+    }
+}
+
+/// View definitions keyed by the lowercased parts of their name. Loaded views
+/// are used to inline those reading from protected tables, so that rewrites
+/// of the table also apply when it is queried through a view.
+pub struct ViewCatalog {
+    views: RwLock<HashMap<Vec<String>, Arc<View>>>,
+}
+
+impl ViewCatalog {
+    pub fn new() -> Self {
+        ViewCatalog {
+            views: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn insert(&self, name: Vec<String>, view: View) {
+        let mut views = self.views.write().unwrap_or_else(PoisonError::into_inner);
+        views.insert(name, Arc::new(view));
+    }
+
+    pub fn remove(&self, name: &[String]) {
+        let mut views = self.views.write().unwrap_or_else(PoisonError::into_inner);
+        views.remove(name);
+    }
+
+    /// Return the views reading from any of the tables, either directly or
+    /// through other views. A name without a schema is in `public`, so a
+    /// view over `public.orders` is returned for `orders`.
+    pub fn protecting(&self, tables: &[Vec<String>]) -> HashMap<Vec<String>, Arc<View>> {
+        let views = self.views.read().unwrap_or_else(PoisonError::into_inner);
+        let mut protecting: HashMap<Vec<String>, Arc<View>> = HashMap::new();
+
+        // repeat until no more views are found, rather than recursing, since
+        // views in the catalog may refer to each other in a cycle
+        loop {
+            let mut found = false;
+            for (name, view) in views.iter() {
+                if protecting.contains_key(name) {
+                    continue;
+                }
+                let reads_protected = view.relations.iter().any(|relation| {
+                    tables.iter().any(|table| same_table(table, relation))
+                        || protecting
+                            .keys()
+                            .any(|view_name| same_table(view_name, relation))
+                });
+                if reads_protected {
+                    protecting.insert(name.clone(), view.clone());
+                    found = true;
+                }
+            }
+            if !found {
+                return protecting;
+            }
+        }
+    }
+}
@@ -0,0 +1,3 @@
+mod sqlcommenter;
+
+pub use self::sqlcommenter::{append_tags, parse_tags};
@@ -0,0 +1,111 @@
+/// Parse the body of a block comment in the sqlcommenter format, such as
+/// `application='x',route='%2Fusers'`. Keys and values are URL decoded, and
+/// `\'` within a value is an escaped quote. Returns None for any comment not
+/// in this format, so that ordinary comments are ignored.
+pub fn parse_tags(comment: &str) -> Option<Vec<(String, String)>> {
+    let comment = comment.trim();
+    if comment.is_empty() {
+        return None;
+    }
+
+    let mut tags = vec![];
+    let mut rest = comment;
+    loop {
+        let (key, after_key) = rest.split_once("='")?;
+        let key = percent_decode(key.trim())?;
+        if key.is_empty() {
+            return None;
+        }
+
+        // find the closing quote, skipping escaped ones
+        let bytes = after_key.as_bytes();
+        let mut end = None;
+        let mut i = 0;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'\\' => i += 2,
+                b'\'' => {
+                    end = Some(i);
+                    break;
+                }
+                _ => i += 1,
+            }
+        }
+        let end = end?;
+        let value = percent_decode(&after_key[..end].replace("\\'", "'"))?;
+        tags.push((key, value));
+
+        rest = after_key[end + 1..].trim_start();
+        match rest.strip_prefix(',') {
+            Some(next) => rest = next.trim_start(),
+            None if rest.is_empty() => return Some(tags),
+            None => return None,
+        }
+    }
+}
+
+/// Append tags to SQL as a sqlcommenter comment. When the SQL already ends
+/// with a sqlcommenter comment, such as one sent by the client, the tags are
+/// merged into it instead, replacing any existing tags with the same keys.
+pub fn append_tags(sql: &str, tags: &[(String, String)]) -> String {
+    let trimmed = sql.trim_end();
+    let trailing = trimmed
+        .strip_suffix("*/")
+        .and_then(|rest| rest.rfind("/*").map(|start| (start, &rest[start + 2..])))
+        .filter(|(_, body)| !body.contains("*/"))
+        .and_then(|(start, body)| Some((start, parse_tags(body)?)));
+
+    match trailing {
+        Some((start, mut existing)) => {
+            existing.retain(|(key, _)| !tags.iter().any(|(k, _)| k == key));
+            existing.extend_from_slice(tags);
+            format!("{}{}", &trimmed[..start], format_tags(&existing))
+        }
+        None => format!("{} {}", sql, format_tags(tags)),
+    }
+}
+
+/// Format tags as a sqlcommenter comment, including the comment markers.
+/// Keys are sorted as required by the specification. Quotes are always
+/// percent encoded, so values never need escaping.
+fn format_tags(tags: &[(String, String)]) -> String {
+    let mut tags: Vec<String> = tags
+        .iter()
+        .map(|(key, value)| format!("{}='{}'", percent_encode(key), percent_encode(value)))
+        .collect();
+    tags.sort();
+    format!("/*{}*/", tags.join(","))
+}
+
+fn percent_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn percent_decode(text: &str) -> Option<String> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = text.get(i + 1..i + 3)?;
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            }
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
@@ -0,0 +1,110 @@
+use sqlparser::keywords::Keyword;
+use sqlparser::tokenizer::{Token, TokenWithLocation, Word};
+
+/// Rewrite PostgreSQL syntax that `PostgreSqlDialect` rejects but that clients
+/// commonly send into equivalent tokens that it accepts:
+///
+/// - the postfix `expr ISNULL` and `expr NOTNULL` operators become `IS NULL`
+///   and `IS NOT NULL`
+/// - `~~`, `~~*`, `!~~` and `!~~*`, the operator forms of LIKE and ILIKE,
+///   become the keywords they stand for, which have the same precedence
+/// - boolean COPY options written as `on`, `off`, `1` or `0`, such as
+///   `HEADER on`, become TRUE or FALSE
+///
+/// The parser only treats its own dialects as PostgreSQL, so this is done on
+/// the tokens instead of through a custom dialect. Statements that have no
+/// representation in the AST, such as LOCK TABLE or CREATE POLICY, still
+/// can't be parsed and are left to the token scan.
+pub fn normalize_tokens(tokens: Vec<TokenWithLocation>) -> Vec<TokenWithLocation> {
+    let mut normalized = Vec::with_capacity(tokens.len());
+    let mut in_copy = false;
+    let mut statement_start = true;
+    let mut previous: Option<Token> = None;
+    let mut tokens = tokens.into_iter().peekable();
+
+    while let Some(token) = tokens.next() {
+        if let Token::Whitespace(_) = token.token {
+            normalized.push(token);
+            continue;
+        }
+        if statement_start {
+            in_copy = is_keyword(&token.token, Keyword::COPY);
+        }
+        statement_start = token.token == Token::SemiColon;
+
+        let location = token.location;
+        let replacement: &[&str] = match (&previous, &token.token) {
+            // `t.isnull` is a column rather than an operator
+            (Some(Token::Period), _) => &[],
+            (_, Token::Word(word)) if word.quote_style.is_none() => {
+                match word.value.to_uppercase().as_str() {
+                    "ISNULL" => &["IS", "NULL"],
+                    "NOTNULL" => &["IS", "NOT", "NULL"],
+                    "ON" if in_copy && is_boolean_option(&previous) => &["TRUE"],
+                    "OFF" if in_copy && is_boolean_option(&previous) => &["FALSE"],
+                    _ => &[],
+                }
+            }
+            (_, Token::Number(n, false)) if in_copy && is_boolean_option(&previous) => {
+                match n.as_str() {
+                    "1" => &["TRUE"],
+                    "0" => &["FALSE"],
+                    _ => &[],
+                }
+            }
+            // the tokenizer splits `~~*` into `~` and `~*`, and `!~~` into
+            // `!~` and `~`. Both halves have to be adjacent, since `a ~ ~b`
+            // is a regex match against a bitwise NOT.
+            (_, Token::Tilde | Token::ExclamationMarkTilde) => {
+                let negated = token.token == Token::ExclamationMarkTilde;
+                match tokens.peek().map(|t| &t.token) {
+                    Some(Token::Tilde) => {
+                        tokens.next();
+                        if negated {
+                            &["NOT", "LIKE"]
+                        } else {
+                            &["LIKE"]
+                        }
+                    }
+                    Some(Token::TildeAsterisk) => {
+                        tokens.next();
+                        if negated {
+                            &["NOT", "ILIKE"]
+                        } else {
+                            &["ILIKE"]
+                        }
+                    }
+                    _ => &[],
+                }
+            }
+            _ => &[],
+        };
+
+        previous = Some(token.token.clone());
+        if replacement.is_empty() {
+            normalized.push(token);
+            continue;
+        }
+        for keyword in replacement {
+            normalized.push(TokenWithLocation::new(
+                Token::make_keyword(keyword),
+                location.line,
+                location.column,
+            ));
+        }
+    }
+    normalized
+}
+
+/// Whether the token is an unquoted keyword.
+fn is_keyword(token: &Token, keyword: Keyword) -> bool {
+    matches!(token, Token::Word(Word { keyword: k, quote_style: None, .. }) if *k == keyword)
+}
+
+/// Whether the token names a COPY option that takes a boolean.
+fn is_boolean_option(token: &Option<Token>) -> bool {
+    match token {
+        Some(token) => is_keyword(token, Keyword::HEADER) || is_keyword(token, Keyword::FREEZE),
+        None => false,
+    }
+}
@@ -0,0 +1,104 @@
+use encoding_rs::Encoding;
+use std::borrow::Cow;
+
+/// The character set a client sends queries in, as set by its
+/// `client_encoding` parameter. Queries are transcoded to UTF-8 for parsing
+/// and back to the client encoding when they are turned into SQL again.
+#[derive(Clone, Copy)]
+pub struct ClientEncoding {
+    encoding: &'static Encoding,
+}
+
+impl ClientEncoding {
+    pub fn utf8() -> Self {
+        ClientEncoding {
+            encoding: encoding_rs::UTF_8,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.encoding.name()
+    }
+
+    /// Look up an encoding by its PostgreSQL name, such as `LATIN1` or
+    /// `WIN1252`. Names are matched ignoring case, dashes and underscores.
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        let normalized: String = name
+            .chars()
+            .filter(|c| *c != '_' && *c != '-')
+            .collect::<String>()
+            .to_uppercase();
+
+        let encoding = match normalized.as_str() {
+            "UTF8" | "UNICODE" => encoding_rs::UTF_8,
+            // SQL_ASCII doesn't define a character set and passes any byte
+            // through as-is. x-user-defined keeps ASCII and maps every other
+            // byte to its own private use character, so queries round trip
+            // without being valid in any particular encoding.
+            "SQLASCII" => encoding_rs::X_USER_DEFINED,
+            // the web's LATIN1 is a superset that only differs for C1 controls
+            "LATIN1" | "ISO88591" => encoding_rs::WINDOWS_1252,
+            "LATIN2" | "ISO88592" => encoding_rs::ISO_8859_2,
+            "LATIN3" | "ISO88593" => encoding_rs::ISO_8859_3,
+            "LATIN4" | "ISO88594" => encoding_rs::ISO_8859_4,
+            "LATIN5" | "ISO88599" => encoding_rs::WINDOWS_1254,
+            "LATIN6" | "ISO885910" => encoding_rs::ISO_8859_10,
+            "LATIN7" | "ISO885913" => encoding_rs::ISO_8859_13,
+            "LATIN8" | "ISO885914" => encoding_rs::ISO_8859_14,
+            "LATIN9" | "ISO885915" => encoding_rs::ISO_8859_15,
+            "LATIN10" | "ISO885916" => encoding_rs::ISO_8859_16,
+            "ISO88595" => encoding_rs::ISO_8859_5,
+            "ISO88596" => encoding_rs::ISO_8859_6,
+            "ISO88597" => encoding_rs::ISO_8859_7,
+            "ISO88598" => encoding_rs::ISO_8859_8,
+            "WIN866" | "ALT" => encoding_rs::IBM866,
+            "WIN874" => encoding_rs::WINDOWS_874,
+            "WIN1250" => encoding_rs::WINDOWS_1250,
+            "WIN1251" | "WIN" => encoding_rs::WINDOWS_1251,
+            "WIN1252" => encoding_rs::WINDOWS_1252,
+            "WIN1253" => encoding_rs::WINDOWS_1253,
+            "WIN1254" => encoding_rs::WINDOWS_1254,
+            "WIN1255" => encoding_rs::WINDOWS_1255,
+            "WIN1256" => encoding_rs::WINDOWS_1256,
+            "WIN1257" => encoding_rs::WINDOWS_1257,
+            "WIN1258" | "ABC" | "TCVN" | "TCVN5712" => encoding_rs::WINDOWS_1258,
+            "KOI8" | "KOI8R" => encoding_rs::KOI8_R,
+            "KOI8U" => encoding_rs::KOI8_U,
+            "SJIS" | "SHIFTJIS" | "MSKANJI" => encoding_rs::SHIFT_JIS,
+            "EUCJP" => encoding_rs::EUC_JP,
+            "EUCKR" | "UHC" => encoding_rs::EUC_KR,
+            "EUCCN" | "GBK" => encoding_rs::GBK,
+            "GB18030" => encoding_rs::GB18030,
+            "BIG5" => encoding_rs::BIG5,
+            _ => return Err(format!("unsupported client encoding {}", name)),
+        };
+        Ok(ClientEncoding { encoding })
+    }
+
+    /// Decode a query into UTF-8 text. Bytes that aren't valid in the
+    /// encoding are an error rather than being replaced, since a query that
+    /// was changed while decoding could no longer be trusted.
+    pub fn decode<'a>(&self, bytes: &'a [u8]) -> Result<Cow<'a, str>, String> {
+        self.encoding
+            .decode_without_bom_handling_and_without_replacement(bytes)
+            .ok_or_else(|| format!("query is not valid {}", self.encoding.name()))
+    }
+
+    /// Encode SQL text back into the client encoding. This can only fail when
+    /// a rewrite added characters that the encoding can't represent.
+    pub fn encode<'a>(&self, sql: &'a str) -> Result<Cow<'a, [u8]>, String> {
+        if self.encoding == encoding_rs::UTF_8 {
+            return Ok(Cow::Borrowed(sql.as_bytes()));
+        }
+
+        let (bytes, _, unmappable) = self.encoding.encode(sql);
+        if unmappable {
+            Err(format!(
+                "query can't be encoded as {}",
+                self.encoding.name()
+            ))
+        } else {
+            Ok(bytes)
+        }
+    }
+}
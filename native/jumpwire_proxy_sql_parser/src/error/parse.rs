@@ -0,0 +1,95 @@
+use rustler::NifMap;
+
+/// Details of a query that couldn't be parsed. Positions are only known for
+/// errors raised by the tokenizer or parser, and are omitted for errors at
+/// the end of the query.
+#[derive(NifMap)]
+pub struct ParseError {
+    pub message: String,
+    /// Suggested SQLSTATE for an ErrorResponse sent back to the client.
+    pub sqlstate: String,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+    /// Byte offset of the error in the UTF-8 query.
+    pub offset: Option<usize>,
+    /// Character position of the error starting at 1, as used by the position
+    /// field of a PostgreSQL ErrorResponse.
+    pub position: Option<usize>,
+    /// The token that couldn't be parsed.
+    pub token: Option<String>,
+}
+
+impl ParseError {
+    pub fn new(message: String, sqlstate: &str) -> Self {
+        ParseError {
+            message,
+            sqlstate: sqlstate.to_string(),
+            line: None,
+            column: None,
+            offset: None,
+            position: None,
+            token: None,
+        }
+    }
+
+    /// Build an error from a message produced by sqlparser, such as
+    /// `Expected end of statement, found: foo at Line: 1, Column 12`,
+    /// locating the position it refers to within the query.
+    pub fn located(message: String, sqlstate: &str, sql: &str) -> Self {
+        let (message, location) = match message.rfind(" at Line: ") {
+            Some(index) => {
+                let location = parse_location(&message[index + 10..]);
+                (message[..index].to_string(), location)
+            }
+            None => (message, None),
+        };
+
+        let token = message
+            .rfind("found: ")
+            .map(|index| message[index + 7..].to_string());
+
+        let mut err = ParseError::new(message, sqlstate);
+        err.token = token;
+        if let Some((line, column)) = location {
+            err.line = Some(line);
+            err.column = Some(column);
+            if let Some((offset, position)) = locate(sql, line, column) {
+                err.offset = Some(offset);
+                err.position = Some(position);
+            }
+        }
+        err
+    }
+}
+
+/// Parse the `1, Column 12` part of a sqlparser location.
+fn parse_location(text: &str) -> Option<(u32, u32)> {
+    let (line, column) = text.split_once(", Column ")?;
+    Some((line.trim().parse().ok()?, column.trim().parse().ok()?))
+}
+
+/// Convert a line and column, both starting at 1, into a byte offset and a
+/// character position. Lines are only split on newlines, matching the
+/// tokenizer.
+fn locate(sql: &str, line: u32, column: u32) -> Option<(usize, usize)> {
+    let (mut current_line, mut current_column) = (1, 1);
+    let mut position = 1;
+    for (offset, c) in sql.char_indices() {
+        if current_line == line && current_column == column {
+            return Some((offset, position));
+        }
+        if c == '\n' {
+            current_line += 1;
+            current_column = 1;
+        } else {
+            current_column += 1;
+        }
+        position += 1;
+    }
+
+    if current_line == line && current_column == column {
+        Some((sql.len(), position))
+    } else {
+        None
+    }
+}
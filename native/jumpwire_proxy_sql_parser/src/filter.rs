@@ -1,3 +1,3 @@
-mod table;
+mod blind_index;
 
-pub use self::table::TableFilterVisit;
+pub use self::blind_index::{BlindIndex, BlindIndexKey};
@@ -0,0 +1,389 @@
+use crate::literal::{comparison, literal_value, with_literal_value};
+use crate::matcher::{Qualifiers, TableMatch};
+use crate::walk::{walk_statement, Edits, QueryVisitor};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use sqlparser::ast::{
+    visit_statements_mut, Assignment, Expr, Ident, OnConflictAction, OnInsert, Query, Select,
+    SetExpr, Statement, Value,
+};
+use std::ops::ControlFlow;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Secret key used to compute blind index values.
+pub struct BlindIndexKey {
+    key: Vec<u8>,
+}
+
+impl BlindIndexKey {
+    pub fn new(key: Vec<u8>) -> Self {
+        BlindIndexKey { key }
+    }
+
+    /// Compute the blind index of a literal value as a hex encoded HMAC-SHA256
+    /// of its text. NULL is indexed as NULL, and values without text such as
+    /// placeholders can't be indexed at all.
+    pub fn digest(&self, value: &Value) -> Option<Value> {
+        let text = match value {
+            Value::Null => return Some(Value::Null),
+            Value::Number(n, _) => n.clone(),
+            Value::Boolean(b) => b.to_string(),
+            Value::DollarQuotedString(s) => s.value.clone(),
+            Value::SingleQuotedString(s)
+            | Value::DoubleQuotedString(s)
+            | Value::EscapedStringLiteral(s)
+            | Value::NationalStringLiteral(s)
+            | Value::UnQuotedString(s) => s.clone(),
+            _ => return None,
+        };
+
+        let mut mac = HmacSha256::new_from_slice(&self.key).ok()?;
+        mac.update(text.as_bytes());
+        let hex = mac
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        Some(Value::SingleQuotedString(hex))
+    }
+}
+
+/// Trait to rewrite a statement so that encrypted columns are searched and
+/// written through their blind index columns. Each index is a pair of the
+/// encrypted column and the column holding its HMAC. For example, with
+/// `(email, email_bidx)`:
+///
+/// - `WHERE email = 'a@b.c'` becomes `WHERE email_bidx = '<hmac>'`
+/// - `INSERT INTO users (email) VALUES ('a@b.c')` also inserts `email_bidx`
+/// - `UPDATE users SET email = 'a@b.c'` also sets `email_bidx`
+///
+/// Predicates are indexed wherever they appear, including join conditions,
+/// CASE expressions, function arguments and subqueries. A column compared to
+/// another column rather than a value is left alone.
+///
+/// Placeholders can't be indexed, since their values are only known once
+/// they are bound. Parameterized statements have to write or compare the
+/// index column themselves, eg `INSERT INTO users (email, email_bidx) VALUES
+/// ($1, $2)`, which is left alone because the index column is already set.
+pub trait BlindIndex {
+    fn blind_index(
+        &mut self,
+        _: &[String],
+        _: &[(String, String)],
+        _: &BlindIndexKey,
+    ) -> Result<(), &'static str>;
+}
+
+impl BlindIndex for Statement {
+    fn blind_index(
+        &mut self,
+        table: &[String],
+        indexes: &[(String, String)],
+        key: &BlindIndexKey,
+    ) -> Result<(), &'static str> {
+        let mut indexer = PredicateIndexer::new(table, indexes, key);
+        walk_statement(self, &mut indexer);
+        if let Some(reason) = indexer.error {
+            return Err(reason);
+        }
+        indexer.edits.apply(self);
+
+        // inserts and updates can also be nested, such as in a CTE
+        let mut result = Ok(());
+        let _ = visit_statements_mut(self, |statement| {
+            result = index_statement(statement, table, indexes, key);
+            match result {
+                Ok(()) => ControlFlow::Continue(()),
+                Err(_) => ControlFlow::Break(()),
+            }
+        });
+        result
+    }
+}
+
+impl BlindIndex for OnInsert {
+    fn blind_index(
+        &mut self,
+        _table: &[String],
+        indexes: &[(String, String)],
+        key: &BlindIndexKey,
+    ) -> Result<(), &'static str> {
+        match self {
+            OnInsert::DuplicateKeyUpdate(assignments) => {
+                index_assignments(assignments, indexes, key)
+            }
+            OnInsert::OnConflict(conflict) => match conflict.action {
+                OnConflictAction::DoUpdate(ref mut update) => {
+                    index_assignments(&mut update.assignments, indexes, key)
+                }
+                _ => Ok(()),
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<T: BlindIndex> BlindIndex for Option<T> {
+    fn blind_index(
+        &mut self,
+        table: &[String],
+        indexes: &[(String, String)],
+        key: &BlindIndexKey,
+    ) -> Result<(), &'static str> {
+        match self {
+            None => Ok(()),
+            Some(x) => x.blind_index(table, indexes, key),
+        }
+    }
+}
+
+/// Write the index columns along with the indexed columns of an INSERT or
+/// UPDATE on the table.
+fn index_statement(
+    statement: &mut Statement,
+    table: &[String],
+    indexes: &[(String, String)],
+    key: &BlindIndexKey,
+) -> Result<(), &'static str> {
+    match statement {
+        Statement::Insert {
+            table_name,
+            columns,
+            source,
+            on,
+            ..
+        } => {
+            if !table_name.matches(table) {
+                return Ok(());
+            }
+            // without a column list there's no telling where the
+            // indexed columns are
+            if columns.is_empty() {
+                return Err("unsupported_statement");
+            }
+
+            for (column, index_column) in indexes.iter() {
+                let position = columns
+                    .iter()
+                    .position(|c| c.value.to_lowercase() == *column);
+                let indexed = columns
+                    .iter()
+                    .any(|c| c.value.to_lowercase() == *index_column);
+                let position = match position {
+                    Some(position) if !indexed => position,
+                    _ => continue,
+                };
+
+                // values can only be indexed when they are part of the query
+                let values = match *source.body {
+                    SetExpr::Values(ref mut values) => values,
+                    _ => return Err("unsupported_insert_source"),
+                };
+
+                let digests = values
+                    .rows
+                    .iter()
+                    .map(|row| match row.get(position) {
+                        Some(value) => digest(value, key),
+                        None => Err("unsupported_value"),
+                    })
+                    .collect::<Result<Vec<Value>, _>>()?;
+
+                for (row, digest) in values.rows.iter_mut().zip(digests) {
+                    row.push(Expr::Value(digest));
+                }
+                columns.push(Ident::new(index_column.clone()));
+            }
+
+            on.blind_index(table, indexes, key)
+        }
+        Statement::Update {
+            table: update_table,
+            assignments,
+            ..
+        } => {
+            if update_table.relation.matches(table) {
+                index_assignments(assignments, indexes, key)
+            } else {
+                Ok(())
+            }
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Collects edits pointing each equality or IN predicate on an indexed
+/// column at its index column, with the values replaced by their digests.
+/// The first predicate that can't be indexed stops any edits from being
+/// made.
+struct PredicateIndexer<'a> {
+    table: &'a [String],
+    indexes: &'a [(String, String)],
+    columns: Vec<String>,
+    key: &'a BlindIndexKey,
+    /// Qualifiers of the table in each enclosing statement, query and SELECT.
+    scopes: Vec<Vec<String>>,
+    edits: Edits,
+    error: Option<&'static str>,
+}
+
+impl<'a> PredicateIndexer<'a> {
+    fn new(table: &'a [String], indexes: &'a [(String, String)], key: &'a BlindIndexKey) -> Self {
+        PredicateIndexer {
+            table,
+            indexes,
+            columns: indexes.iter().map(|(column, _)| column.clone()).collect(),
+            key,
+            scopes: vec![],
+            edits: Edits::default(),
+            error: None,
+        }
+    }
+
+    fn index_predicate(&mut self, expr: &Expr) -> Result<(), &'static str> {
+        let qualifiers = match self.scopes.last() {
+            Some(qualifiers) => qualifiers,
+            None => return Ok(()),
+        };
+        let comparison = match comparison(expr, qualifiers, &self.columns) {
+            Some(comparison) => comparison,
+            None => return Ok(()),
+        };
+        let index_column = match self.indexes.iter().find(|(c, _)| *c == comparison.column) {
+            Some((_, index_column)) => index_column,
+            None => return Ok(()),
+        };
+
+        // a column compared to another column isn't searching for a value,
+        // but it can't be swapped for its index while an IN list still
+        // holds values that aren't indexed
+        let values: Vec<Option<Value>> = comparison
+            .operands
+            .iter()
+            .map(|operand| literal_value(operand))
+            .collect();
+        if !comparison.list && values.iter().all(Option::is_none) {
+            return Ok(());
+        }
+
+        for (operand, value) in comparison.operands.iter().zip(values) {
+            let digest = value
+                .and_then(|value| self.key.digest(&value))
+                .ok_or("unsupported_value")?;
+            self.edits
+                .replace(operand, with_literal_value(operand, digest))
+        }
+
+        let mut target = comparison.target.clone();
+        match target {
+            Expr::Identifier(ref mut ident) => *ident = Ident::new(index_column.clone()),
+            Expr::CompoundIdentifier(ref mut idents) => {
+                if let Some(ident) = idents.last_mut() {
+                    *ident = Ident::new(index_column.clone())
+                }
+            }
+            _ => (),
+        }
+        self.edits.replace(comparison.target, target);
+        Ok(())
+    }
+}
+
+impl<'a> QueryVisitor for PredicateIndexer<'a> {
+    fn enter_statement(&mut self, statement: &Statement) {
+        let mut qualifiers = vec![];
+        match statement {
+            Statement::Update { table, from, .. } => {
+                table.qualifiers(self.table, &mut qualifiers);
+                from.qualifiers(self.table, &mut qualifiers)
+            }
+            Statement::Delete { from, using, .. } => {
+                from.qualifiers(self.table, &mut qualifiers);
+                using.qualifiers(self.table, &mut qualifiers)
+            }
+            _ => (),
+        }
+        self.scopes.push(qualifiers)
+    }
+
+    fn exit_statement(&mut self, _statement: &Statement) {
+        self.scopes.pop();
+    }
+
+    fn enter_query(&mut self, _query: &Query) {
+        self.scopes.push(vec![])
+    }
+
+    fn exit_query(&mut self, _query: &Query) {
+        self.scopes.pop();
+    }
+
+    fn enter_select(&mut self, select: &Select) {
+        let mut qualifiers = vec![];
+        select.qualifiers(self.table, &mut qualifiers);
+        self.scopes.push(qualifiers)
+    }
+
+    fn exit_select(&mut self, _select: &Select) {
+        self.scopes.pop();
+    }
+
+    fn enter_expr(&mut self, expr: &Expr) {
+        if self.error.is_none() {
+            self.error = self.index_predicate(expr).err()
+        }
+    }
+}
+
+/// The digest of a literal value, including one behind a sign or a cast.
+fn digest(expr: &Expr, key: &BlindIndexKey) -> Result<Value, &'static str> {
+    literal_value(expr)
+        .and_then(|value| key.digest(&value))
+        .ok_or("unsupported_value")
+}
+
+/// Add an assignment to the index column for every assignment to an indexed
+/// column, unless the index column is already being set explicitly.
+fn index_assignments(
+    assignments: &mut Vec<Assignment>,
+    indexes: &[(String, String)],
+    key: &BlindIndexKey,
+) -> Result<(), &'static str> {
+    let assigned: Vec<String> = assignments
+        .iter()
+        .filter_map(|a| a.id.last())
+        .map(|ident| ident.value.to_lowercase())
+        .collect();
+
+    let mut added = vec![];
+    for assignment in assignments.iter() {
+        let column = match assignment.id.last() {
+            Some(ident) => ident.value.to_lowercase(),
+            None => continue,
+        };
+        let index_column = match indexes.iter().find(|(c, _)| *c == column) {
+            Some((_, index_column)) if !assigned.contains(index_column) => index_column,
+            _ => continue,
+        };
+
+        let value = match assignment.value {
+            // upserts such as `SET email = EXCLUDED.email`
+            Expr::CompoundIdentifier(ref idents)
+                if idents.len() == 2 && idents[0].value.to_lowercase() == "excluded" =>
+            {
+                Expr::CompoundIdentifier(vec![idents[0].clone(), Ident::new(index_column.clone())])
+            }
+            ref value => Expr::Value(digest(value, key)?),
+        };
+
+        let mut id = assignment.id.clone();
+        id.pop();
+        id.push(Ident::new(index_column.clone()));
+        added.push(Assignment { id, value });
+    }
+
+    assignments.extend(added);
+    Ok(())
+}
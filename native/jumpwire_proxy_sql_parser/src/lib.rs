@@ -1,14 +1,57 @@
-use crate::filter::TableFilterVisit;
-use rustler::{Atom, Binary, Env, Error, NifResult, NifUnitEnum, ResourceArc, Term, TermType};
-use serde_rustler::prefixed_to_term;
-use sqlparser::ast::{visit_statements_mut, BinaryOperator, Expr, Ident, Statement, Value};
+use crate::bulk::BulkInsert;
+use crate::cache::{CacheStats, ParseCache};
+use crate::catalog::{View, ViewCatalog};
+use crate::comment::{append_tags, parse_tags};
+use crate::dialect::normalize_tokens;
+use crate::encoding::ClientEncoding;
+use crate::error::ParseError;
+use crate::filter::{BlindIndex, BlindIndexKey};
+use crate::literal::{find_values, replace_values, LiteralKind};
+use crate::parameter::{parameter_columns, ParameterColumn};
+use crate::prepared::{PreparedRegistry, PreparedStatement};
+use crate::projection::{project, Projection, ProjectionField};
+use crate::resource::StatementResource;
+use crate::rewrite::{
+    apply_rules, object_name, table_key, FilterRule, QualifyRule, RewriteRule, RuleRegistry,
+    SubstituteRule, ViewRule,
+};
+use crate::scan::{scan_tokens, ScanResult};
+use crate::split::split_statements;
+use crate::validate::{column_violations, ViolationKind};
+use rustler::{
+    Atom, Binary, Encoder, Env, Error, NifMap, NifResult, NifUnitEnum, OwnedBinary, ResourceArc,
+    Term, TermType,
+};
+use serde::Serialize;
+use serde_rustler::{from_term, prefixed_to_term, to_term};
+use sqlparser::ast::{BinaryOperator, CopyTarget, Expr, Ident, Statement, Value};
 use sqlparser::dialect::{GenericDialect, MySqlDialect, PostgreSqlDialect};
 use sqlparser::parser::{Parser, ParserError};
-use std::ops::ControlFlow;
-use std::sync::Mutex;
+use sqlparser::tokenizer::{Token, Tokenizer, Whitespace};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Arc;
 
+mod bulk;
+mod cache;
+mod catalog;
+mod comment;
+mod dialect;
+mod encoding;
+mod error;
 mod filter;
+mod literal;
 mod matcher;
+mod parameter;
+mod prepared;
+mod projection;
+mod render;
+mod resource;
+mod rewrite;
+mod scan;
+mod split;
+mod validate;
+mod walk;
 
 mod atoms {
     rustler::atoms! {
@@ -17,7 +60,19 @@ mod atoms {
         tokenizer_error,
         parser_error,
         recursion_limit_exceeded,
+        query_too_large,
+        too_many_tokens,
+        invalid_encoding,
+        unsupported_encoding,
+        not_bulk_insert,
         mutex_locked,
+        unsupported_statement,
+        unsupported_insert_source,
+        unsupported_value,
+        not_found,
+        invalid_statement,
+        invalid_op,
+        invalid_view,
     }
 }
 
@@ -35,72 +90,558 @@ enum Dialect {
     Generic,
 }
 
+/// Description of a bulk insert returned to Elixir.
+#[derive(NifMap)]
+struct BulkInsertInfo {
+    schema: Option<String>,
+    table: String,
+    columns: Vec<String>,
+    rows: usize,
+}
+
+/// Limits on the size and complexity of a query that will be parsed. Unset
+/// limits fall back to the parser defaults.
+#[derive(NifMap, Default)]
+struct ParseLimits {
+    max_bytes: Option<usize>,
+    max_depth: Option<usize>,
+    max_tokens: Option<usize>,
+}
+
 #[rustler::nif]
-fn debug_parse(query: Binary, dialect: Dialect) -> String {
+fn debug_parse(query: Binary, dialect: Dialect) -> Result<String, (Atom, ParseError)> {
     let dialect: Box<dyn sqlparser::dialect::Dialect> = match dialect {
         Dialect::Postgresql => Box::new(PostgreSqlDialect {}),
         Dialect::Mysql => Box::new(MySqlDialect {}),
         Dialect::Generic => Box::new(GenericDialect {}),
     };
 
-    let sql = std::str::from_utf8(query.as_slice()).unwrap();
-    let parsed = Parser::parse_sql(&*dialect, sql).unwrap();
-    format!("{parsed:?}")
+    let sql = decode_query(ClientEncoding::utf8(), &query)?;
+    let parsed = Parser::parse_sql(&*dialect, &sql).map_err(|err| parse_error(err, &sql))?;
+    Ok(format!("{parsed:?}"))
 }
 
+/// Parse a query within the given limits.
 #[rustler::nif]
-fn parse_postgresql<'a>(
+fn parse_postgresql_limited<'a>(
     env: Env<'a>,
     query: Binary,
-) -> Result<Vec<(Term<'a>, ResourceArc<StatementResource>)>, (Atom, String)> {
-    let sql = std::str::from_utf8(query.as_slice()).unwrap();
-    let parsed_result = parse(sql);
-    let statements = match parsed_result {
-        Ok(s) => s,
-        Err(err) => {
-            let err = match err {
-                ParserError::TokenizerError(err) => (atoms::tokenizer_error(), err),
-                ParserError::ParserError(err) => (atoms::parser_error(), err),
-                ParserError::RecursionLimitExceeded => {
-                    (atoms::recursion_limit_exceeded(), String::from(""))
-                }
-            };
-            return Err(err);
+    encoding: String,
+    limits: ParseLimits,
+) -> Result<StatementTerms<'a>, (Atom, ParseError)> {
+    let encoding = client_encoding(&encoding)?;
+    parse_limited(env, &query, encoding, &limits)
+}
+
+/// Same as `parse_postgresql_limited`, for large queries that would otherwise
+/// block a normal scheduler for too long.
+#[rustler::nif(schedule = "DirtyCpu")]
+fn parse_postgresql_limited_dirty<'a>(
+    env: Env<'a>,
+    query: Binary,
+    encoding: String,
+    limits: ParseLimits,
+) -> Result<StatementTerms<'a>, (Atom, ParseError)> {
+    let encoding = client_encoding(&encoding)?;
+    parse_limited(env, &query, encoding, &limits)
+}
+
+#[rustler::nif]
+fn parse_postgresql_projected(
+    query: Binary,
+    encoding: String,
+    limits: ParseLimits,
+    fields: Vec<ProjectionField>,
+) -> Result<ProjectedStatements, (Atom, ParseError)> {
+    let encoding = client_encoding(&encoding)?;
+    parse_projected(&query, encoding, &limits, &fields)
+}
+
+#[rustler::nif(schedule = "DirtyCpu")]
+fn parse_postgresql_projected_dirty(
+    query: Binary,
+    encoding: String,
+    limits: ParseLimits,
+    fields: Vec<ProjectionField>,
+) -> Result<ProjectedStatements, (Atom, ParseError)> {
+    let encoding = client_encoding(&encoding)?;
+    parse_projected(&query, encoding, &limits, &fields)
+}
+
+/// Serialize the current statement of a resource, for callers that parsed
+/// it with a projection and later need the full AST.
+#[rustler::nif]
+fn statement_term<'a>(
+    env: Env<'a>,
+    resource: ResourceArc<StatementResource>,
+) -> Result<Term<'a>, (Atom, ParseError)> {
+    serialize_statements(env, &*resource.snapshot())
+}
+
+/// Serialize the current statement of a resource in the lossless form used
+/// by `replace_statement`. Unlike the structs returned when parsing, every
+/// enum keeps its variant so the term can be converted back into an AST.
+#[rustler::nif]
+fn statement_ast<'a>(
+    env: Env<'a>,
+    resource: ResourceArc<StatementResource>,
+) -> Result<Term<'a>, (Atom, ParseError)> {
+    to_term(env, &*resource.snapshot()).map_err(|err| {
+        let msg: String = err.into();
+        (atoms::error(), ParseError::new(msg, "XX000"))
+    })
+}
+
+/// Convert a term from `statement_ast`, possibly modified in Elixir, back
+/// into a statement and store it in the resource.
+#[rustler::nif]
+fn replace_statement<'a>(
+    resource: ResourceArc<StatementResource>,
+    ast: Term<'a>,
+) -> NifResult<Atom> {
+    let statement: Statement = from_term(ast).map_err(|err| {
+        let msg: String = err.into();
+        let err = ParseError::new(msg, "XX000");
+        Error::Term(Box::new((atoms::invalid_statement(), err)))
+    })?;
+    resource.replace(statement);
+    Ok(atoms::ok())
+}
+
+fn parse_limited<'a>(
+    env: Env<'a>,
+    query: &[u8],
+    encoding: ClientEncoding,
+    limits: &ParseLimits,
+) -> Result<StatementTerms<'a>, (Atom, ParseError)> {
+    check_query_size(query, limits)?;
+    let sql = decode_query(encoding, query)?;
+
+    // statements are only cached when parsed with the default recursion
+    // limit, since a lower limit could reject a cached statement
+    let cache = ParseCache::global();
+    let cacheable = limits.max_depth.is_none() && cache.cacheable(&sql);
+    if cacheable {
+        if let Some(cached) = cache.get(env, &sql, "postgresql", encoding.name()) {
+            check_token_limit(cached.tokens, limits)?;
+            let resources = sourced_resources(&sql, cached.statements, encoding);
+            return statement_resources(cached.term, resources);
+        }
+    }
+
+    let (statements, count) = parse_checked(&sql, limits)?;
+    let term = serialize_statements(env, &statements)?;
+    let statements: Vec<Arc<Statement>> = statements.into_iter().map(Arc::new).collect();
+    if cacheable {
+        let name = encoding.name();
+        cache.insert(&sql, "postgresql", name, statements.clone(), count, term);
+    }
+    let resources = sourced_resources(&sql, statements, encoding);
+    statement_resources(term, resources)
+}
+
+/// Parse a query into statement resources along with a projection of each
+/// statement, without serializing the AST. Statements are read from the parse
+/// cache when possible but are never added to it, since the cache holds the
+/// serialized terms.
+fn parse_projected(
+    query: &[u8],
+    encoding: ClientEncoding,
+    limits: &ParseLimits,
+    fields: &[ProjectionField],
+) -> Result<ProjectedStatements, (Atom, ParseError)> {
+    check_query_size(query, limits)?;
+    let sql = decode_query(encoding, query)?;
+
+    let cache = ParseCache::global();
+    let cached = if limits.max_depth.is_none() && cache.cacheable(&sql) {
+        cache.statements(&sql, "postgresql", encoding.name())
+    } else {
+        None
+    };
+    let statements = match cached {
+        Some((statements, count)) => {
+            check_token_limit(count, limits)?;
+            statements
+        }
+        None => {
+            let (statements, _) = parse_checked(&sql, limits)?;
+            statements.into_iter().map(Arc::new).collect()
         }
     };
 
-    let prefix = "Elixir.JumpWire.Proxy.SQL.Statement.";
+    let projections: Vec<Projection> = statements.iter().map(|s| project(s, fields)).collect();
+    let resources = sourced_resources(&sql, statements, encoding);
+    let projected = projections
+        .into_iter()
+        .zip(resources.into_iter().map(ResourceArc::new))
+        .collect();
+    Ok(projected)
+}
+
+/// Tokenize and parse a query within the given limits, returning the
+/// statements and the number of tokens excluding whitespace.
+fn parse_checked(
+    sql: &str,
+    limits: &ParseLimits,
+) -> Result<(Vec<Statement>, usize), (Atom, ParseError)> {
+    let dialect = PostgreSqlDialect {};
+    let tokens = Tokenizer::new(&dialect, sql)
+        .tokenize_with_location()
+        .map_err(|err| parse_error(err.into(), sql))?;
+    let count = tokens
+        .iter()
+        .filter(|t| !matches!(t.token, Token::Whitespace(_)))
+        .count();
+    check_token_limit(count, limits)?;
+
+    let mut parser = Parser::new(&dialect).with_tokens_with_locations(normalize_tokens(tokens));
+    if let Some(max_depth) = limits.max_depth {
+        parser = parser.with_recursion_limit(max_depth);
+    }
+    let statements = parser
+        .parse_statements()
+        .map_err(|err| parse_error(err, sql))?;
+    Ok((statements, count))
+}
 
-    match prefixed_to_term(env, &statements, prefix) {
-        Ok(term) => {
-            let resources = statements.into_iter().map(|s| {
-                let resource = StatementResource {
-                    statement: Mutex::new(s),
-                };
-                ResourceArc::new(resource)
-            });
-            let res = term.into_list_iterator().unwrap().zip(resources).collect();
-            Ok(res)
+fn check_query_size(query: &[u8], limits: &ParseLimits) -> Result<(), (Atom, ParseError)> {
+    match limits.max_bytes {
+        Some(max_bytes) if query.len() > max_bytes => {
+            let msg = format!("query is {} bytes, the limit is {}", query.len(), max_bytes);
+            Err((atoms::query_too_large(), ParseError::new(msg, "54000")))
         }
-        Err(err) => {
-            let msg: String = err.into();
-            Err((atoms::error(), msg))
+        _ => Ok(()),
+    }
+}
+
+fn check_token_limit(count: usize, limits: &ParseLimits) -> Result<(), (Atom, ParseError)> {
+    match limits.max_tokens {
+        Some(max_tokens) if count > max_tokens => {
+            let msg = format!("query has {} tokens, the limit is {}", count, max_tokens);
+            Err((atoms::too_many_tokens(), ParseError::new(msg, "54000")))
         }
+        _ => Ok(()),
+    }
+}
+
+/// Set the number of queries kept in the parse cache and the size in bytes
+/// of the largest query that is cached.
+#[rustler::nif]
+fn configure_parse_cache(capacity: usize, max_query_bytes: usize) -> Atom {
+    ParseCache::global().configure(capacity, max_query_bytes);
+    atoms::ok()
+}
+
+/// Return hit and miss counts of the parse cache.
+#[rustler::nif]
+fn parse_cache_stats() -> CacheStats {
+    ParseCache::global().stats()
+}
+
+#[rustler::nif]
+fn clear_parse_cache() -> Atom {
+    ParseCache::global().clear();
+    atoms::ok()
+}
+
+/// Find the relations and columns referenced by a query using only the
+/// tokenizer, for queries that the parser doesn't support.
+#[rustler::nif]
+fn scan_postgresql(query: Binary, encoding: String) -> Result<ScanResult, (Atom, ParseError)> {
+    let encoding = client_encoding(&encoding)?;
+    let sql = decode_query(encoding, &query)?;
+    let dialect = PostgreSqlDialect {};
+    let tokens = Tokenizer::new(&dialect, &sql)
+        .tokenize()
+        .map_err(|err| parse_error(err.into(), &sql))?;
+    Ok(scan_tokens(tokens))
+}
+
+/// Recognize an `INSERT ... VALUES` statement made up only of literal values
+/// without building an AST. Any other query returns `not_bulk_insert`, and
+/// should be parsed normally instead.
+#[rustler::nif(schedule = "DirtyCpu")]
+fn parse_bulk_insert_query(
+    query: Binary,
+    encoding: String,
+    standard_conforming_strings: bool,
+) -> Result<(ResourceArc<BulkInsert>, BulkInsertInfo), (Atom, ParseError)> {
+    let encoding = client_encoding(&encoding)?;
+    let sql = decode_query(encoding, &query)?.into_owned();
+    let insert =
+        BulkInsert::parse(sql, encoding, standard_conforming_strings).ok_or_else(|| {
+            let msg = String::from("query is not an INSERT of literal VALUES");
+            (atoms::not_bulk_insert(), ParseError::new(msg, "0A000"))
+        })?;
+
+    let info = BulkInsertInfo {
+        schema: insert.schema.clone(),
+        table: insert.table.clone(),
+        columns: insert.columns.clone(),
+        rows: insert.rows,
+    };
+    Ok((ResourceArc::new(insert), info))
+}
+
+/// Return up to `limit` values of a column of a bulk insert as `{row, value}`,
+/// starting at the row `offset`.
+#[rustler::nif]
+fn bulk_insert_values<'a>(
+    env: Env<'a>,
+    resource: ResourceArc<BulkInsert>,
+    column: String,
+    offset: usize,
+    limit: usize,
+) -> NifResult<Vec<(usize, Term<'a>)>> {
+    let column = resource
+        .column_index(&column)
+        .ok_or(Error::Atom("unknown_column"))?;
+    let prefix = "Elixir.JumpWire.Proxy.SQL.Statement.";
+    resource
+        .values(column, offset, limit)
+        .into_iter()
+        .map(|(row, value)| {
+            let term = prefixed_to_term(env, &value, prefix).map_err(|err| {
+                let msg: String = err.into();
+                Error::Term(Box::new(msg))
+            })?;
+            Ok((row, term))
+        })
+        .collect()
+}
+
+/// Replace values of a column of a bulk insert, given as `{row, value}`.
+#[rustler::nif]
+fn replace_bulk_insert_values<'a>(
+    resource: ResourceArc<BulkInsert>,
+    column: String,
+    replacements: Vec<(usize, Term<'a>)>,
+) -> NifResult<Atom> {
+    let column = resource
+        .column_index(&column)
+        .ok_or(Error::Atom("unknown_column"))?;
+    let replacements = replacement_values(replacements)?;
+    resource
+        .replace(column, replacements)
+        .map_err(Error::Atom)?;
+    Ok(atoms::ok())
+}
+
+#[rustler::nif(schedule = "DirtyCpu")]
+fn bulk_insert_to_sql<'a>(
+    env: Env<'a>,
+    resource: ResourceArc<BulkInsert>,
+) -> NifResult<(Atom, Binary<'a>)> {
+    let sql = resource.to_sql();
+    encode_sql(env, resource.encoding(), &sql)
+}
+
+/// Split a query into the `{start, length}` byte range of each statement.
+#[rustler::nif]
+fn split_query(query: Binary, standard_conforming_strings: bool) -> Vec<(usize, usize)> {
+    split_statements(query.as_slice(), standard_conforming_strings)
+}
+
+/// Same as `split_query`, for large queries.
+#[rustler::nif(schedule = "DirtyCpu")]
+fn split_query_dirty(query: Binary, standard_conforming_strings: bool) -> Vec<(usize, usize)> {
+    split_statements(query.as_slice(), standard_conforming_strings)
+}
+
+fn client_encoding(name: &str) -> Result<ClientEncoding, (Atom, ParseError)> {
+    ClientEncoding::from_name(name)
+        .map_err(|msg| (atoms::unsupported_encoding(), ParseError::new(msg, "22023")))
+}
+
+fn decode_query<'a>(
+    encoding: ClientEncoding,
+    query: &'a [u8],
+) -> Result<Cow<'a, str>, (Atom, ParseError)> {
+    encoding
+        .decode(query)
+        .map_err(|msg| (atoms::invalid_encoding(), ParseError::new(msg, "22021")))
+}
+
+/// Convert a parser error into an error atom and details for Elixir. Syntax
+/// errors include their position within the query.
+fn parse_error(err: ParserError, sql: &str) -> (Atom, ParseError) {
+    match err {
+        ParserError::TokenizerError(msg) => (
+            atoms::tokenizer_error(),
+            ParseError::located(msg, "42601", sql),
+        ),
+        ParserError::ParserError(msg) => (
+            atoms::parser_error(),
+            ParseError::located(msg, "42601", sql),
+        ),
+        ParserError::RecursionLimitExceeded => (
+            atoms::recursion_limit_exceeded(),
+            ParseError::new(String::from("query is nested too deeply"), "54001"),
+        ),
     }
 }
 
-struct StatementResource {
-    pub statement: Mutex<Statement>,
+/// Parsed statements as Elixir terms, each paired with a resource holding the
+/// statement for later rewrites.
+type StatementTerms<'a> = Vec<(Term<'a>, ResourceArc<StatementResource>)>;
+
+/// Projections of parsed statements, each paired with a resource holding the
+/// full statement.
+type ProjectedStatements = Vec<(Projection, ResourceArc<StatementResource>)>;
+
+fn serialize_statements<'a, T: Serialize + ?Sized>(
+    env: Env<'a>,
+    statements: &T,
+) -> Result<Term<'a>, (Atom, ParseError)> {
+    let prefix = "Elixir.JumpWire.Proxy.SQL.Statement.";
+    prefixed_to_term(env, statements, prefix).map_err(|err| {
+        let msg: String = err.into();
+        (atoms::error(), ParseError::new(msg, "XX000"))
+    })
+}
+
+/// Pair each term of a serialized list of statements with its resource.
+fn statement_resources<'a>(
+    term: Term<'a>,
+    resources: Vec<StatementResource>,
+) -> Result<StatementTerms<'a>, (Atom, ParseError)> {
+    let resources = resources.into_iter().map(ResourceArc::new);
+    let terms = term.into_list_iterator().map_err(|_| {
+        let msg = String::from("statements are not a list");
+        (atoms::error(), ParseError::new(msg, "XX000"))
+    })?;
+    Ok(terms.zip(resources).collect())
+}
+
+/// Create resources for statements parsed from a query, keeping the text of
+/// each statement so that rewrites can be spliced into it. The text is only
+/// kept when splitting the query finds the same number of statements.
+fn sourced_resources(
+    sql: &str,
+    statements: Vec<Arc<Statement>>,
+    encoding: ClientEncoding,
+) -> Vec<StatementResource> {
+    let ranges = split_statements(sql.as_bytes(), true);
+    let sourced = ranges.len() == statements.len();
+    statements
+        .into_iter()
+        .enumerate()
+        .map(|(i, s)| {
+            let resource = StatementResource::shared(s, encoding);
+            match sourced {
+                true => {
+                    let (start, len) = ranges[i];
+                    resource.with_source(&sql[start..start + len])
+                }
+                false => resource,
+            }
+        })
+        .collect()
 }
 
 #[rustler::nif]
-fn to_sql(resource: ResourceArc<StatementResource>) -> NifResult<(Atom, String)> {
-    let statement = resource
-        .statement
-        .try_lock()
-        .map_err(|_| Error::Atom("mutex_lock_failure"))?;
+fn to_sql<'a>(
+    env: Env<'a>,
+    resource: ResourceArc<StatementResource>,
+) -> NifResult<(Atom, Binary<'a>)> {
+    let statement = resource.snapshot();
     let sql = format!("{}", statement);
-    Ok((atoms::ok(), sql))
+    encode_sql(
+        env,
+        resource.encoding(),
+        &with_tags(sql, &statement, &resource),
+    )
+}
+
+/// Convert a statement back into SQL, splicing any rewrites into the text it
+/// was parsed from so that the rest of the query is sent unchanged. Falls
+/// back to rendering the whole statement when the text isn't known.
+#[rustler::nif]
+fn splice_sql<'a>(
+    env: Env<'a>,
+    resource: ResourceArc<StatementResource>,
+) -> NifResult<(Atom, Binary<'a>)> {
+    let statement = resource.snapshot();
+    let sql = match resource.source() {
+        Some((source, original)) => render::splice_sql(source, original, &statement),
+        None => statement.to_string(),
+    };
+    encode_sql(
+        env,
+        resource.encoding(),
+        &with_tags(sql, &statement, &resource),
+    )
+}
+
+/// Append the sqlcommenter tags of a resource to its SQL, if it has any.
+/// Tags are never added to COPY FROM STDIN, since its data is rendered
+/// after the statement.
+fn with_tags(sql: String, statement: &Statement, resource: &StatementResource) -> String {
+    let tags = resource.tags();
+    let from_stdin = matches!(
+        statement,
+        Statement::Copy {
+            to: false,
+            target: CopyTarget::Stdin,
+            ..
+        }
+    );
+    match tags.is_empty() || from_stdin {
+        true => sql,
+        false => append_tags(&sql, &tags),
+    }
+}
+
+/// Find the sqlcommenter tags in the comments of a query. Tags from later
+/// comments replace those with the same key from earlier ones.
+#[rustler::nif]
+fn sql_comment_tags(
+    query: Binary,
+    encoding: String,
+) -> Result<HashMap<String, String>, (Atom, ParseError)> {
+    let encoding = client_encoding(&encoding)?;
+    let sql = decode_query(encoding, &query)?;
+    let dialect = PostgreSqlDialect {};
+    let tokens = Tokenizer::new(&dialect, &sql)
+        .tokenize()
+        .map_err(|err| parse_error(err.into(), &sql))?;
+
+    let tags = tokens
+        .iter()
+        .filter_map(|token| match token {
+            Token::Whitespace(Whitespace::MultiLineComment(comment)) => parse_tags(comment),
+            _ => None,
+        })
+        .flatten()
+        .collect();
+    Ok(tags)
+}
+
+/// Add sqlcommenter tags to be appended to a statement when it is converted
+/// back into SQL.
+#[rustler::nif]
+fn put_sql_comment_tags(
+    resource: ResourceArc<StatementResource>,
+    tags: HashMap<String, String>,
+) -> Atom {
+    resource.add_tags(tags.into_iter().collect());
+    atoms::ok()
+}
+
+/// Encode SQL text into the client encoding as an Elixir binary.
+fn encode_sql<'a>(
+    env: Env<'a>,
+    encoding: ClientEncoding,
+    sql: &str,
+) -> NifResult<(Atom, Binary<'a>)> {
+    let bytes = encoding
+        .encode(sql)
+        .map_err(|msg| Error::Term(Box::new((atoms::invalid_encoding(), msg))))?;
+
+    let mut binary = OwnedBinary::new(bytes.len()).ok_or(Error::Atom("allocation_failure"))?;
+    binary.as_mut_slice().copy_from_slice(&bytes);
+    Ok((atoms::ok(), binary.release(env)))
 }
 
 #[rustler::nif]
@@ -115,14 +656,7 @@ fn add_table_selection<'a>(
         value: left,
         quote_style: None,
     });
-    let right = match right.get_type() {
-        TermType::Integer | TermType::Float => {
-            let term: i32 = right.decode()?;
-            Expr::Value(Value::Number(term.to_string(), false))
-        }
-        TermType::Binary => Expr::Value(Value::SingleQuotedString(right.decode()?)),
-        _ => return Err(Error::Atom("invalid_value")),
-    };
+    let right = Expr::Value(term_to_value(right)?);
 
     let selection = match op {
         BinaryOp::Eq => Expr::BinaryOp {
@@ -133,34 +667,531 @@ fn add_table_selection<'a>(
         _ => return Err(Error::Atom("unknown_operator")),
     };
 
-    let mut statement = resource
-        .statement
-        .try_lock()
-        .map_err(|_| Error::Atom("mutex_lock_failure"))?;
-
     // TODO: better matching for the Ident, allow table name to have a specified namespace
     let table_ident = vec![table.to_lowercase()];
 
     // find all selections, create a where clause or modify it if possible
-    visit_statements_mut(&mut *statement, |stmt| {
-        stmt.visit(&table_ident, &selection);
-        ControlFlow::<()>::Continue(())
-    });
+    resource.rewrite(|statement| {
+        let rule = FilterRule::new(table_ident.clone(), selection.clone());
+        apply_rules(statement, &mut [Box::new(rule) as Box<dyn RewriteRule>]);
+        Ok(atoms::ok())
+    })
+}
+
+/// Start a transaction for speculative rewrites. The returned statement is
+/// rewritten in place of the original, and the changes are only applied to
+/// the original by `commit_rewrite`.
+#[rustler::nif]
+fn begin_rewrite(resource: ResourceArc<StatementResource>) -> ResourceArc<StatementResource> {
+    ResourceArc::new(resource.begin())
+}
+
+/// Apply all rewrites of a transaction to the statement it was started from.
+/// Returns `:conflict` if that statement was rewritten in the meantime.
+#[rustler::nif]
+fn commit_rewrite(
+    resource: ResourceArc<StatementResource>,
+    transaction: ResourceArc<StatementResource>,
+) -> NifResult<Atom> {
+    resource.commit(&transaction).map_err(Error::Atom)?;
     Ok(atoms::ok())
 }
 
-fn parse(sql: &str) -> Result<Vec<Statement>, ParserError> {
-    let dialect = PostgreSqlDialect {};
-    Parser::parse_sql(&dialect, sql)
+/// Undo all rewrites of a transaction since it was started.
+#[rustler::nif]
+fn rollback_rewrite(transaction: ResourceArc<StatementResource>) -> NifResult<Atom> {
+    transaction.rollback().map_err(Error::Atom)?;
+    Ok(atoms::ok())
+}
+
+/// Literal values found in a statement, each with its position and the
+/// column it belongs to.
+type LiteralTerms<'a> = Vec<(usize, String, Term<'a>)>;
+
+/// Find literal values written to any of the given columns of a table by an
+/// INSERT or UPDATE statement. Each value is returned with its position so
+/// that it can be replaced with `replace_column_values`, along with the
+/// version of the statement the positions refer to.
+#[rustler::nif]
+fn find_column_values<'a>(
+    env: Env<'a>,
+    resource: ResourceArc<StatementResource>,
+    table: String,
+    columns: Vec<String>,
+) -> NifResult<(u64, LiteralTerms<'a>)> {
+    find_literals(env, &resource, LiteralKind::Written, table, columns)
+}
+
+/// Replace literal values found by `find_column_values`. The table and
+/// columns must be the same as the ones used to find the values, and the
+/// statement must still be at the version they were found in. Otherwise
+/// the positions may point at different values, and `stale` is returned.
+#[rustler::nif]
+fn replace_column_values<'a>(
+    resource: ResourceArc<StatementResource>,
+    version: u64,
+    table: String,
+    columns: Vec<String>,
+    replacements: Vec<(usize, Term<'a>)>,
+) -> NifResult<Atom> {
+    let kind = LiteralKind::Written;
+    replace_literals(&resource, kind, version, table, columns, replacements)
+}
+
+/// Find literal values compared against any of the given columns of a table
+/// in an equality or IN predicate, in the same shape as `find_column_values`.
+#[rustler::nif]
+fn find_predicate_values<'a>(
+    env: Env<'a>,
+    resource: ResourceArc<StatementResource>,
+    table: String,
+    columns: Vec<String>,
+) -> NifResult<(u64, LiteralTerms<'a>)> {
+    find_literals(env, &resource, LiteralKind::Compared, table, columns)
+}
+
+/// Replace literal values found by `find_predicate_values`, under the same
+/// conditions as `replace_column_values`.
+#[rustler::nif]
+fn replace_predicate_values<'a>(
+    resource: ResourceArc<StatementResource>,
+    version: u64,
+    table: String,
+    columns: Vec<String>,
+    replacements: Vec<(usize, Term<'a>)>,
+) -> NifResult<Atom> {
+    let kind = LiteralKind::Compared;
+    replace_literals(&resource, kind, version, table, columns, replacements)
+}
+
+/// Encode the literal values of a statement as `{index, column, value}`
+/// tuples, along with the version of the statement they were found in.
+fn find_literals<'a>(
+    env: Env<'a>,
+    resource: &StatementResource,
+    kind: LiteralKind,
+    table: String,
+    columns: Vec<String>,
+) -> NifResult<(u64, LiteralTerms<'a>)> {
+    let (version, statement) = resource.versioned();
+
+    let table_ident = vec![table.to_lowercase()];
+    let columns: Vec<String> = columns.iter().map(|c| c.to_lowercase()).collect();
+    let values = find_values(&statement, kind, &table_ident, &columns);
+
+    let prefix = "Elixir.JumpWire.Proxy.SQL.Statement.";
+    let terms = values
+        .into_iter()
+        .enumerate()
+        .map(|(index, (column, value))| {
+            let term = prefixed_to_term(env, &value, prefix).map_err(|err| {
+                let msg: String = err.into();
+                Error::Term(Box::new(msg))
+            })?;
+            Ok((index, column, term))
+        })
+        .collect::<NifResult<_>>()?;
+    Ok((version, terms))
+}
+
+/// Replace literal values by their position, failing with `stale` if the
+/// statement changed since the version they were found in.
+fn replace_literals(
+    resource: &StatementResource,
+    kind: LiteralKind,
+    version: u64,
+    table: String,
+    columns: Vec<String>,
+    replacements: Vec<(usize, Term)>,
+) -> NifResult<Atom> {
+    // decode every replacement before touching the statement so that an
+    // invalid value doesn't leave it partially rewritten
+    let replacements = replacement_values(replacements)?;
+
+    let table_ident = vec![table.to_lowercase()];
+    let columns: Vec<String> = columns.iter().map(|c| c.to_lowercase()).collect();
+    resource
+        .rewrite_at(version, |statement| {
+            replace_values(statement, kind, &table_ident, &columns, &replacements)
+        })
+        .map_err(Error::Atom)?;
+    Ok(atoms::ok())
+}
+
+/// Apply several rewrites to a statement in a single traversal. Each op is
+/// one of:
+///
+/// - `{:filter, table, column, :eq, value}`
+/// - `{:mask, table, column, value}`
+/// - `{:limit, max}`
+/// - `{:substitute, table, replacement}`
+/// - `{:qualify, schema}`
+/// - `{:expand_views, catalog, tables}`
+///
+/// Returns the number of places each op was applied, in the same order. An op
+/// that can't be built is returned as `{:error, {:invalid_op, op}}`, and a
+/// statement that a rule can't rewrite safely as
+/// `{:error, {:unsupported_statement, msg}}`.
+#[rustler::nif]
+fn apply_rewrites<'a>(
+    env: Env<'a>,
+    resource: ResourceArc<StatementResource>,
+    ops: Vec<Term<'a>>,
+) -> Result<Vec<usize>, (Atom, Term<'a>)> {
+    let registry = RuleRegistry::global();
+    resource.rewrite(|statement| {
+        // rules count where they were applied, so they are created again if
+        // the rewrite has to be retried
+        let mut rules = ops
+            .iter()
+            .map(|op| registry.build(*op).map_err(|_| (atoms::invalid_op(), *op)))
+            .collect::<Result<Vec<_>, _>>()?;
+        apply_rules(statement, &mut rules);
+        if let Some(msg) = rules.iter().find_map(|rule| rule.rejection()) {
+            return Err((atoms::unsupported_statement(), msg.encode(env)));
+        }
+        Ok(rules.iter().map(|rule| rule.applied()).collect())
+    })
+}
+
+/// Point table references at other tables. Each `{table, replacement}` pair
+/// redirects a table, keeping its name as an alias. If a schema is given, any
+/// table still referenced without one is then qualified with it.
+///
+/// Returns the number of references rewritten.
+#[rustler::nif]
+fn rewrite_table_names(
+    resource: ResourceArc<StatementResource>,
+    tables: Vec<(String, String)>,
+    schema: Option<String>,
+) -> NifResult<(Atom, usize)> {
+    let count = resource.rewrite(|statement| {
+        let mut rules: Vec<Box<dyn RewriteRule>> = tables
+            .iter()
+            .map(|(table, replacement)| {
+                Box::new(SubstituteRule::new(
+                    table_key(table),
+                    object_name(replacement),
+                )) as Box<dyn RewriteRule>
+            })
+            .collect();
+        if let Some(schema) = &schema {
+            rules.push(Box::new(QualifyRule::new(object_name(schema))));
+        }
+        apply_rules(statement, &mut rules);
+        Ok::<_, Error>(rules.iter().map(|rule| rule.applied()).sum())
+    })?;
+    Ok((atoms::ok(), count))
+}
+
+/// Create a key resource used to compute blind index values.
+#[rustler::nif]
+fn blind_index_key(key: Binary) -> ResourceArc<BlindIndexKey> {
+    ResourceArc::new(BlindIndexKey::new(key.as_slice().to_vec()))
+}
+
+/// Rewrite predicates, inserts and updates on encrypted columns to use their
+/// blind index columns. Indexes are given as `{column, index_column}` pairs.
+#[rustler::nif]
+fn add_blind_index(
+    resource: ResourceArc<StatementResource>,
+    key: ResourceArc<BlindIndexKey>,
+    table: String,
+    indexes: Vec<(String, String)>,
+) -> NifResult<Atom> {
+    let table_ident = vec![table.to_lowercase()];
+    let indexes: Vec<(String, String)> = indexes
+        .into_iter()
+        .map(|(column, index)| (column.to_lowercase(), index.to_lowercase()))
+        .collect();
+
+    // a failure leaves the statement untouched rather than half indexed
+    resource.rewrite(|statement| {
+        statement
+            .blind_index(&table_ident, &indexes, &key)
+            .map_err(blind_index_error)?;
+        Ok(atoms::ok())
+    })
+}
+
+/// Describe why a statement couldn't be indexed, returned as `{reason, details}`.
+fn blind_index_error(reason: &'static str) -> Error {
+    let (reason, msg) = match reason {
+        "unsupported_statement" => (
+            atoms::unsupported_statement(),
+            "INSERT statements must list their columns to be indexed",
+        ),
+        "unsupported_insert_source" => (
+            atoms::unsupported_insert_source(),
+            "only INSERT statements with VALUES can be indexed",
+        ),
+        _ => (
+            atoms::unsupported_value(),
+            "only literal values can be indexed",
+        ),
+    };
+    let err = ParseError::new(String::from(msg), "0A000");
+    Error::Term(Box::new((reason, err)))
+}
+
+/// Find operations on encrypted or tokenized columns of a table that would
+/// silently operate on the protected data, such as ORDER BY or LIKE. Each
+/// violation is returned as `{kind, column, message}`.
+#[rustler::nif]
+fn validate_columns(
+    resource: ResourceArc<StatementResource>,
+    table: String,
+    columns: Vec<String>,
+) -> NifResult<Vec<(ViolationKind, String, String)>> {
+    let statement = resource.snapshot();
+    let table_ident = vec![table.to_lowercase()];
+    let columns: Vec<String> = columns.iter().map(|c| c.to_lowercase()).collect();
+    let violations = column_violations(&statement, &table_ident, &columns)
+        .into_iter()
+        .map(|v| {
+            let message = v.message();
+            (v.kind, v.column, message)
+        })
+        .collect();
+    Ok(violations)
+}
+
+/// Map each placeholder in a prepared statement to the column it is compared
+/// to, inserted into or assigned to.
+#[rustler::nif]
+fn parameter_targets(resource: ResourceArc<StatementResource>) -> NifResult<Vec<ParameterColumn>> {
+    Ok(parameter_columns(&resource.snapshot()))
+}
+
+/// Create a registry for the prepared statements of a client connection.
+#[rustler::nif]
+fn prepared_registry() -> ResourceArc<PreparedRegistry> {
+    ResourceArc::new(PreparedRegistry::new())
+}
+
+/// Parse the query from a Parse message within the given limits and store it
+/// in the registry under the statement name. The result is the same as
+/// `parse_postgresql_limited`.
+#[rustler::nif]
+fn prepare_statement_limited<'a>(
+    env: Env<'a>,
+    registry: ResourceArc<PreparedRegistry>,
+    name: String,
+    query: Binary,
+    param_types: Vec<u32>,
+    encoding: String,
+    limits: ParseLimits,
+) -> Result<StatementTerms<'a>, (Atom, ParseError)> {
+    let encoding = client_encoding(&encoding)?;
+    prepare_limited(env, &registry, name, &query, param_types, encoding, &limits)
+}
+
+/// Same as `prepare_statement_limited`, for large queries that would otherwise
+/// block a normal scheduler for too long.
+#[rustler::nif(schedule = "DirtyCpu")]
+fn prepare_statement_limited_dirty<'a>(
+    env: Env<'a>,
+    registry: ResourceArc<PreparedRegistry>,
+    name: String,
+    query: Binary,
+    param_types: Vec<u32>,
+    encoding: String,
+    limits: ParseLimits,
+) -> Result<StatementTerms<'a>, (Atom, ParseError)> {
+    let encoding = client_encoding(&encoding)?;
+    prepare_limited(env, &registry, name, &query, param_types, encoding, &limits)
+}
+
+fn prepare_limited<'a>(
+    env: Env<'a>,
+    registry: &PreparedRegistry,
+    name: String,
+    query: &[u8],
+    param_types: Vec<u32>,
+    encoding: ClientEncoding,
+    limits: &ParseLimits,
+) -> Result<StatementTerms<'a>, (Atom, ParseError)> {
+    let terms = parse_limited(env, query, encoding, limits)?;
+    let resources = terms.iter().map(|(_, resource)| &**resource);
+    registry.insert(
+        name,
+        PreparedStatement::new(resources, param_types, encoding),
+    );
+    Ok(terms)
+}
+
+/// A prepared statement as `{:ok, statements, param_types, parameters}`.
+type PreparedTerms<'a> = (Atom, StatementTerms<'a>, Vec<u32>, Vec<ParameterColumn>);
+
+/// Look up a prepared statement by name. Each call returns new statement
+/// resources, so rewrites from one execution don't affect the next.
+#[rustler::nif]
+fn fetch_prepared<'a>(
+    env: Env<'a>,
+    registry: ResourceArc<PreparedRegistry>,
+    name: String,
+) -> NifResult<PreparedTerms<'a>> {
+    let prepared = registry.get(&name).ok_or_else(|| {
+        let msg = format!("prepared statement \"{name}\" does not exist");
+        Error::Term(Box::new((
+            atoms::not_found(),
+            ParseError::new(msg, "26000"),
+        )))
+    })?;
+    let statements: Vec<&Statement> = prepared.statements.iter().map(|(s, _)| &**s).collect();
+    let statements = serialize_statements(env, &statements)
+        .and_then(|term| statement_resources(term, prepared.resources()))
+        .map_err(|err| Error::Term(Box::new(err)))?;
+    Ok((
+        atoms::ok(),
+        statements,
+        prepared.param_types.clone(),
+        prepared.parameters.clone(),
+    ))
+}
+
+/// Remove a prepared statement, such as when the client sends a Close message.
+#[rustler::nif]
+fn close_prepared(registry: ResourceArc<PreparedRegistry>, name: String) -> NifResult<Atom> {
+    registry.remove(&name);
+    Ok(atoms::ok())
+}
+
+/// Create a catalog for the view definitions of a database.
+#[rustler::nif]
+fn view_catalog() -> ResourceArc<ViewCatalog> {
+    ResourceArc::new(ViewCatalog::new())
+}
+
+/// Parse the definition of a view and store it in the catalog, replacing any
+/// view with the same name. The definition can be the query of the view, such
+/// as from `pg_views`, or a `CREATE VIEW` statement.
+#[rustler::nif]
+fn load_view(
+    catalog: ResourceArc<ViewCatalog>,
+    name: String,
+    definition: String,
+) -> NifResult<Atom> {
+    let (mut statements, _) = parse_checked(&definition, &ParseLimits::default())
+        .map_err(|err| Error::Term(Box::new(err)))?;
+    let view = match statements.len() {
+        1 => View::from_statement(statements.remove(0)),
+        _ => None,
+    };
+    let view = view.ok_or_else(|| {
+        let msg = format!("{} is not a view definition", name);
+        let err = ParseError::new(msg, "42809");
+        Error::Term(Box::new((atoms::invalid_view(), err)))
+    })?;
+    catalog.insert(table_key(&name), view);
+    Ok(atoms::ok())
+}
+
+/// Remove a view from the catalog, such as after it is dropped.
+#[rustler::nif]
+fn remove_view(catalog: ResourceArc<ViewCatalog>, name: String) -> NifResult<Atom> {
+    catalog.remove(&table_key(&name));
+    Ok(atoms::ok())
+}
+
+/// Inline the views in the catalog that read from any of the tables, directly
+/// or through other views, as derived tables. Filters on those tables then
+/// apply to queries made through the views.
+///
+/// Returns the number of views inlined.
+#[rustler::nif]
+fn expand_views(
+    resource: ResourceArc<StatementResource>,
+    catalog: ResourceArc<ViewCatalog>,
+    tables: Vec<String>,
+) -> NifResult<(Atom, usize)> {
+    let tables: Vec<Vec<String>> = tables.iter().map(|table| table_key(table)).collect();
+    let views = catalog.protecting(&tables);
+    let count = resource.rewrite(|statement| {
+        let mut rules = [Box::new(ViewRule::new(views.clone())) as Box<dyn RewriteRule>];
+        apply_rules(statement, &mut rules);
+        Ok::<_, Error>(rules[0].applied())
+    })?;
+    Ok((atoms::ok(), count))
+}
+
+/// Decode replacement values before touching the statement.
+fn replacement_values(replacements: Vec<(usize, Term)>) -> NifResult<Vec<(usize, Value)>> {
+    replacements
+        .into_iter()
+        .map(|(index, term)| Ok((index, term_to_value(term)?)))
+        .collect()
+}
+
+/// Convert an Elixir term into a literal SQL value.
+fn term_to_value(term: Term) -> NifResult<Value> {
+    match term.get_type() {
+        TermType::Integer | TermType::Float => {
+            let term: i32 = term.decode()?;
+            Ok(Value::Number(term.to_string(), false))
+        }
+        TermType::Binary => Ok(Value::SingleQuotedString(term.decode()?)),
+        _ => Err(Error::Atom("invalid_value")),
+    }
 }
 
 fn load(env: Env, _: Term) -> bool {
     rustler::resource!(StatementResource, env);
+    rustler::resource!(BlindIndexKey, env);
+    rustler::resource!(PreparedRegistry, env);
+    rustler::resource!(BulkInsert, env);
+    rustler::resource!(ViewCatalog, env);
     true
 }
 
 rustler::init!(
     "Elixir.JumpWire.Proxy.SQL.Parser",
-    [parse_postgresql, debug_parse, to_sql, add_table_selection],
+    [
+        parse_postgresql_limited,
+        parse_postgresql_limited_dirty,
+        scan_postgresql,
+        split_query,
+        split_query_dirty,
+        parse_bulk_insert_query,
+        bulk_insert_values,
+        replace_bulk_insert_values,
+        bulk_insert_to_sql,
+        configure_parse_cache,
+        parse_postgresql_projected,
+        parse_postgresql_projected_dirty,
+        statement_term,
+        statement_ast,
+        replace_statement,
+        parse_cache_stats,
+        clear_parse_cache,
+        debug_parse,
+        to_sql,
+        splice_sql,
+        sql_comment_tags,
+        put_sql_comment_tags,
+        add_table_selection,
+        begin_rewrite,
+        commit_rewrite,
+        rollback_rewrite,
+        find_column_values,
+        replace_column_values,
+        find_predicate_values,
+        replace_predicate_values,
+        apply_rewrites,
+        rewrite_table_names,
+        view_catalog,
+        load_view,
+        remove_view,
+        expand_views,
+        blind_index_key,
+        add_blind_index,
+        validate_columns,
+        parameter_targets,
+        prepared_registry,
+        prepare_statement_limited,
+        prepare_statement_limited_dirty,
+        fetch_prepared,
+        close_prepared
+    ],
     load = load
 );
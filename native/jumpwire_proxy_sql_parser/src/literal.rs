@@ -0,0 +1,65 @@
+use sqlparser::ast::{Expr, UnaryOperator, Value};
+use std::collections::HashMap;
+
+mod column;
+mod predicate;
+mod values;
+
+pub use self::predicate::comparison;
+pub use self::values::{find_values, replace_values, LiteralKind};
+
+/// Literal values that belong to a column, marked by the node holding them
+/// such as the INSERT writing them or the predicate comparing them. Values are keyed by the address of their
+/// expression, which is stable while a statement is walked.
+#[derive(Default)]
+pub struct Marks(HashMap<*const Expr, String>);
+
+impl Marks {
+    /// Only values that carry data are marked. NULLs have nothing to protect
+    /// and placeholders are bound separately by the extended query protocol.
+    pub fn mark(&mut self, expr: &Expr, column: &str) {
+        match literal_value(expr) {
+            Some(Value::Null | Value::Placeholder(_)) | None => (),
+            Some(_) => {
+                self.0.insert(expr as *const Expr, column.to_string());
+            }
+        }
+    }
+
+    /// Return the column of a marked value once it's reached.
+    pub fn take(&mut self, expr: &Expr) -> Option<String> {
+        self.0.remove(&(expr as *const Expr))
+    }
+}
+
+/// The literal value of an expression, looking through a sign such as `-5`
+/// or a cast such as `'x'::text`.
+pub fn literal_value(expr: &Expr) -> Option<Value> {
+    match expr {
+        Expr::Value(value) => Some(value.clone()),
+        Expr::UnaryOp { op, expr } => match (op, expr.as_ref()) {
+            (UnaryOperator::Minus, Expr::Value(Value::Number(n, long))) => {
+                Some(Value::Number(format!("-{n}"), *long))
+            }
+            (UnaryOperator::Plus, Expr::Value(value @ Value::Number(..))) => Some(value.clone()),
+            _ => None,
+        },
+        Expr::Cast { expr, .. } | Expr::Nested(expr) => literal_value(expr),
+        _ => None,
+    }
+}
+
+/// Replace the literal value of an expression found with `literal_value`,
+/// keeping any cast around it.
+pub fn with_literal_value(expr: &Expr, value: Value) -> Expr {
+    match expr {
+        Expr::Cast { .. } | Expr::Nested(_) => {
+            let mut expr = expr.clone();
+            if let Expr::Cast { expr: inner, .. } | Expr::Nested(inner) = &mut expr {
+                **inner = with_literal_value(inner, value)
+            }
+            expr
+        }
+        _ => Expr::Value(value),
+    }
+}
@@ -0,0 +1,76 @@
+use super::Marks;
+use crate::matcher::TableMatch;
+use sqlparser::ast::{Assignment, OnConflictAction, OnInsert, SetExpr, Statement};
+
+/// Mark the literal values that a statement writes into specific columns of
+/// a table. For example, `INSERT INTO users (name, ssn) VALUES ('a', 'b')`
+/// has a literal `'b'` bound to the `ssn` column.
+pub fn mark_written_values(
+    statement: &Statement,
+    table: &[String],
+    columns: &[String],
+    marks: &mut Marks,
+) {
+    match statement {
+        Statement::Insert {
+            table_name,
+            columns: insert_columns,
+            source,
+            on,
+            ..
+        } => {
+            if !table_name.matches(table) {
+                return;
+            }
+
+            // map each positional value in a row to the column it is inserted into
+            let targets: Vec<Option<String>> = insert_columns
+                .iter()
+                .map(|c| c.value.to_lowercase())
+                .map(|c| if columns.contains(&c) { Some(c) } else { None })
+                .collect();
+
+            if let SetExpr::Values(values) = &*source.body {
+                for row in values.rows.iter() {
+                    for (expr, target) in row.iter().zip(targets.iter()) {
+                        if let Some(column) = target {
+                            marks.mark(expr, column)
+                        }
+                    }
+                }
+            }
+
+            match on {
+                Some(OnInsert::DuplicateKeyUpdate(assignments)) => {
+                    mark_assignments(assignments, columns, marks)
+                }
+                Some(OnInsert::OnConflict(conflict)) => {
+                    if let OnConflictAction::DoUpdate(update) = &conflict.action {
+                        mark_assignments(&update.assignments, columns, marks)
+                    }
+                }
+                _ => (),
+            }
+        }
+        Statement::Update {
+            table: update_table,
+            assignments,
+            ..
+        } if update_table.relation.matches(table) => mark_assignments(assignments, columns, marks),
+        _ => (),
+    }
+}
+
+fn mark_assignments(assignments: &[Assignment], columns: &[String], marks: &mut Marks) {
+    for assignment in assignments.iter() {
+        // assignments may be qualified, eg `SET users.ssn = '...'`
+        let column = match assignment.id.last() {
+            Some(ident) => ident.value.to_lowercase(),
+            None => continue,
+        };
+
+        if columns.contains(&column) {
+            marks.mark(&assignment.value, &column)
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use super::Marks;
+use crate::matcher::column_name;
+use sqlparser::ast::{BinaryOperator, Expr};
+
+/// An equality or IN predicate comparing one of the requested columns, such
+/// as `ssn = '123'` or `ssn IN ('123', '456')`.
+pub struct Comparison<'a> {
+    /// Lowercased name of the column being compared.
+    pub column: String,
+    /// The column reference itself, either an identifier or a compound identifier.
+    pub target: &'a Expr,
+    /// Everything the column is compared to, literal or not.
+    pub operands: Vec<&'a Expr>,
+    /// Whether the operands are alternatives of an IN list rather than the
+    /// other side of an equality.
+    pub list: bool,
+}
+
+/// Return the comparison an expression makes against any of the given
+/// columns. This only looks at the expression itself, and is called for
+/// every expression of a statement while it's walked.
+pub fn comparison<'a>(
+    expr: &'a Expr,
+    qualifiers: &[String],
+    columns: &[String],
+) -> Option<Comparison<'a>> {
+    match expr {
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::Eq | BinaryOperator::NotEq,
+            right,
+        } => {
+            let (column, target, operand) = match column_name(left, qualifiers, columns) {
+                Some(column) => (column, left, right),
+                None => (column_name(right, qualifiers, columns)?, right, left),
+            };
+            Some(Comparison {
+                column,
+                target,
+                operands: vec![operand],
+                list: false,
+            })
+        }
+        Expr::InList { expr, list, .. } => Some(Comparison {
+            column: column_name(expr, qualifiers, columns)?,
+            target: expr,
+            operands: list.iter().collect(),
+            list: true,
+        }),
+        _ => None,
+    }
+}
+
+/// Mark the literal values an expression compares against any of the given
+/// columns.
+pub fn mark_compared_values(
+    expr: &Expr,
+    qualifiers: &[String],
+    columns: &[String],
+    marks: &mut Marks,
+) {
+    if let Some(comparison) = comparison(expr, qualifiers, columns) {
+        for operand in comparison.operands {
+            marks.mark(operand, &comparison.column)
+        }
+    }
+}
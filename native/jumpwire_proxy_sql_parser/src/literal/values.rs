@@ -0,0 +1,154 @@
+use super::column::mark_written_values;
+use super::predicate::mark_compared_values;
+use super::{literal_value, with_literal_value, Marks};
+use crate::matcher::Qualifiers;
+use crate::walk::{walk_statement, Edits, QueryVisitor};
+use sqlparser::ast::{Expr, Query, Select, Statement, Value};
+
+/// Which literal values of a statement belong to the requested columns.
+#[derive(Clone, Copy)]
+pub enum LiteralKind {
+    /// Values written into the columns by an INSERT or UPDATE, such as `'b'`
+    /// in `INSERT INTO users (name, ssn) VALUES ('a', 'b')`.
+    Written,
+    /// Values compared against the columns by an equality or IN predicate,
+    /// such as `'123'` in `WHERE ssn = '123'`.
+    Compared,
+}
+
+/// Find the literal values belonging to any of the given columns of a
+/// table, along with the column each belongs to. The position of a value in
+/// the result is the index used to replace it with `replace_values`.
+pub fn find_values(
+    statement: &Statement,
+    kind: LiteralKind,
+    table: &[String],
+    columns: &[String],
+) -> Vec<(String, Value)> {
+    let mut finder = ValueFinder::new(kind, table, columns, &[]);
+    walk_statement(statement, &mut finder);
+    finder.values
+}
+
+/// Replace literal values by their position as returned by `find_values`
+/// for the same table and columns. Nothing is changed when a position
+/// doesn't exist.
+pub fn replace_values(
+    statement: &mut Statement,
+    kind: LiteralKind,
+    table: &[String],
+    columns: &[String],
+    replacements: &[(usize, Value)],
+) -> Result<(), &'static str> {
+    let mut finder = ValueFinder::new(kind, table, columns, replacements);
+    walk_statement(statement, &mut finder);
+
+    let found = finder.values.len();
+    if replacements.iter().any(|(index, _)| *index >= found) {
+        return Err("invalid_index");
+    }
+    finder.edits.apply(statement);
+    Ok(())
+}
+
+/// Collects the literal values belonging to the columns while a statement is
+/// walked, along with edits replacing the values at the requested positions.
+struct ValueFinder<'a> {
+    kind: LiteralKind,
+    table: &'a [String],
+    columns: &'a [String],
+    /// Qualifiers of the table in each enclosing statement, query and SELECT.
+    scopes: Vec<Vec<String>>,
+    marks: Marks,
+    values: Vec<(String, Value)>,
+    replacements: &'a [(usize, Value)],
+    edits: Edits,
+}
+
+impl<'a> ValueFinder<'a> {
+    fn new(
+        kind: LiteralKind,
+        table: &'a [String],
+        columns: &'a [String],
+        replacements: &'a [(usize, Value)],
+    ) -> Self {
+        ValueFinder {
+            kind,
+            table,
+            columns,
+            scopes: vec![],
+            marks: Marks::default(),
+            values: vec![],
+            replacements,
+            edits: Edits::default(),
+        }
+    }
+}
+
+impl<'a> QueryVisitor for ValueFinder<'a> {
+    fn enter_statement(&mut self, statement: &Statement) {
+        let mut qualifiers = vec![];
+        match statement {
+            Statement::Update { table, from, .. } => {
+                table.qualifiers(self.table, &mut qualifiers);
+                from.qualifiers(self.table, &mut qualifiers)
+            }
+            Statement::Delete { from, using, .. } => {
+                from.qualifiers(self.table, &mut qualifiers);
+                using.qualifiers(self.table, &mut qualifiers)
+            }
+            _ => (),
+        }
+        self.scopes.push(qualifiers);
+
+        if let LiteralKind::Written = self.kind {
+            mark_written_values(statement, self.table, self.columns, &mut self.marks)
+        }
+    }
+
+    fn exit_statement(&mut self, _statement: &Statement) {
+        self.scopes.pop();
+    }
+
+    /// Clauses of a query outside of its SELECTs, such as ORDER BY, don't
+    /// reference the tables of those SELECTs by name.
+    fn enter_query(&mut self, _query: &Query) {
+        self.scopes.push(vec![])
+    }
+
+    fn exit_query(&mut self, _query: &Query) {
+        self.scopes.pop();
+    }
+
+    fn enter_select(&mut self, select: &Select) {
+        let mut qualifiers = vec![];
+        select.qualifiers(self.table, &mut qualifiers);
+        self.scopes.push(qualifiers)
+    }
+
+    fn exit_select(&mut self, _select: &Select) {
+        self.scopes.pop();
+    }
+
+    fn enter_expr(&mut self, expr: &Expr) {
+        if let (LiteralKind::Compared, Some(qualifiers)) = (self.kind, self.scopes.last()) {
+            mark_compared_values(expr, qualifiers, self.columns, &mut self.marks)
+        }
+
+        let column = match self.marks.take(expr) {
+            Some(column) => column,
+            None => return,
+        };
+        let value = match literal_value(expr) {
+            Some(value) => value,
+            None => return,
+        };
+
+        let position = self.values.len();
+        if let Some((_, replacement)) = self.replacements.iter().find(|(i, _)| *i == position) {
+            self.edits
+                .replace(expr, with_literal_value(expr, replacement.clone()))
+        }
+        self.values.push((column, value))
+    }
+}
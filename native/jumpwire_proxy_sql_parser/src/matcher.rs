@@ -1,3 +1,5 @@
+mod column;
 mod table;
 
-pub use self::table::TableMatch;
+pub use self::column::{column_name, Qualifiers};
+pub use self::table::{same_table, TableMatch};
@@ -0,0 +1,112 @@
+use super::TableMatch;
+use sqlparser::ast::{Expr, Query, Select, SetExpr, TableFactor, TableWithJoins};
+
+/// Trait to collect the names that columns of a table can be qualified
+/// with, eg `users` and `u` in `FROM users u`. Nothing is collected when the
+/// table isn't referenced.
+pub trait Qualifiers {
+    fn qualifiers(&self, _: &[String], _: &mut Vec<String>);
+}
+
+impl Qualifiers for TableWithJoins {
+    fn qualifiers(&self, table: &[String], acc: &mut Vec<String>) {
+        self.relation.qualifiers(table, acc);
+        for join in self.joins.iter() {
+            join.relation.qualifiers(table, acc)
+        }
+    }
+}
+
+impl Qualifiers for TableFactor {
+    fn qualifiers(&self, table: &[String], acc: &mut Vec<String>) {
+        match self {
+            TableFactor::Table { name, alias, .. } if name.matches(table) => {
+                if let Some(ident) = name.0.last() {
+                    acc.push(ident.value.to_lowercase())
+                }
+                if let Some(alias) = alias {
+                    acc.push(alias.name.value.to_lowercase())
+                }
+            }
+            TableFactor::NestedJoin {
+                table_with_joins, ..
+            } => table_with_joins.qualifiers(table, acc),
+            _ => (),
+        }
+    }
+}
+
+impl Qualifiers for Select {
+    fn qualifiers(&self, table: &[String], acc: &mut Vec<String>) {
+        self.from.qualifiers(table, acc)
+    }
+}
+
+impl Qualifiers for SetExpr {
+    fn qualifiers(&self, table: &[String], acc: &mut Vec<String>) {
+        match self {
+            SetExpr::Select(select) => select.qualifiers(table, acc),
+            SetExpr::Query(query) => query.qualifiers(table, acc),
+            SetExpr::SetOperation { left, right, .. } => {
+                left.qualifiers(table, acc);
+                right.qualifiers(table, acc)
+            }
+            _ => (),
+        }
+    }
+}
+
+impl Qualifiers for Query {
+    fn qualifiers(&self, table: &[String], acc: &mut Vec<String>) {
+        self.body.qualifiers(table, acc)
+    }
+}
+
+impl<T: Qualifiers> Qualifiers for Box<T> {
+    fn qualifiers(&self, table: &[String], acc: &mut Vec<String>) {
+        (**self).qualifiers(table, acc)
+    }
+}
+
+impl<T: Qualifiers> Qualifiers for Vec<T> {
+    fn qualifiers(&self, table: &[String], acc: &mut Vec<String>) {
+        for x in self.iter() {
+            x.qualifiers(table, acc)
+        }
+    }
+}
+
+impl<T: Qualifiers> Qualifiers for Option<T> {
+    fn qualifiers(&self, table: &[String], acc: &mut Vec<String>) {
+        if let Some(x) = self {
+            x.qualifiers(table, acc)
+        }
+    }
+}
+
+/// Return the name of the column referenced by an expression if it is one of
+/// the requested columns on the matching table. Unqualified names are assumed
+/// to belong to the table whenever it is part of the query.
+pub fn column_name(expr: &Expr, qualifiers: &[String], columns: &[String]) -> Option<String> {
+    if qualifiers.is_empty() {
+        return None;
+    }
+
+    let column = match expr {
+        Expr::Identifier(ident) => ident.value.to_lowercase(),
+        Expr::CompoundIdentifier(idents) if idents.len() >= 2 => {
+            let qualifier = idents[idents.len() - 2].value.to_lowercase();
+            if !qualifiers.contains(&qualifier) {
+                return None;
+            }
+            idents[idents.len() - 1].value.to_lowercase()
+        }
+        _ => return None,
+    };
+
+    if columns.contains(&column) {
+        Some(column)
+    } else {
+        None
+    }
+}
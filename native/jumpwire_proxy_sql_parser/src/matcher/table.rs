@@ -8,8 +8,8 @@ where
     fn matches(&self, other: &Rhs) -> bool;
 }
 
-impl TableMatch<Vec<String>> for TableFactor {
-    fn matches(&self, other: &Vec<String>) -> bool {
+impl TableMatch<[String]> for TableFactor {
+    fn matches(&self, other: &[String]) -> bool {
         match self {
             TableFactor::Table { name, .. } => name.matches(other),
             TableFactor::NestedJoin {
@@ -20,42 +20,54 @@ impl TableMatch<Vec<String>> for TableFactor {
     }
 }
 
-impl TableMatch<Vec<String>> for TableWithJoins {
-    fn matches(&self, other: &Vec<String>) -> bool {
+impl TableMatch<[String]> for TableWithJoins {
+    fn matches(&self, other: &[String]) -> bool {
         self.relation.matches(other) || self.joins.iter().any(|join| join.relation.matches(other))
     }
 }
 
-impl TableMatch<Vec<String>> for ObjectName {
-    fn matches(&self, other: &Vec<String>) -> bool {
+impl TableMatch<[String]> for ObjectName {
+    fn matches(&self, other: &[String]) -> bool {
         self.0.matches(other)
     }
 }
 
-impl TableMatch<Vec<String>> for Select {
-    fn matches(&self, other: &Vec<String>) -> bool {
+impl TableMatch<[String]> for Select {
+    fn matches(&self, other: &[String]) -> bool {
         self.from.matches(other)
     }
 }
 
-impl TableMatch<Vec<String>> for Vec<Ident> {
-    fn matches(&self, other: &Vec<String>) -> bool {
+impl TableMatch<[String]> for Vec<Ident> {
+    fn matches(&self, other: &[String]) -> bool {
         let ident: Vec<String> = self.iter().map(|i| i.value.to_lowercase()).collect();
         ident == *other
     }
 }
 
-impl<T: TableMatch<Vec<String>>> TableMatch<Vec<String>> for Vec<T> {
-    fn matches(&self, other: &Vec<String>) -> bool {
+impl<T: TableMatch<[String]>> TableMatch<[String]> for Vec<T> {
+    fn matches(&self, other: &[String]) -> bool {
         self.iter().any(|x| x.matches(other))
     }
 }
 
-impl<T: TableMatch<Vec<String>>> TableMatch<Vec<String>> for Option<T> {
-    fn matches(&self, other: &Vec<String>) -> bool {
+impl<T: TableMatch<[String]>> TableMatch<[String]> for Option<T> {
+    fn matches(&self, other: &[String]) -> bool {
         match self {
             None => false,
             Some(x) => x.matches(other),
         }
     }
 }
+
+/// Whether two lowercased table names refer to the same table. A name
+/// without a schema is resolved against the default search path, so `orders`
+/// matches `public.orders` but not `reports.orders`.
+pub fn same_table(a: &[String], b: &[String]) -> bool {
+    match (a, b) {
+        ([table], [schema, other]) | ([schema, other], [table]) => {
+            schema == "public" && table == other
+        }
+        _ => !a.is_empty() && a == b,
+    }
+}
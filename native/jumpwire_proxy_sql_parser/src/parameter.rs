@@ -0,0 +1,3 @@
+mod column;
+
+pub use self::column::{parameter_columns, ParameterColumn};
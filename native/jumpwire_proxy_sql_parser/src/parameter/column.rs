@@ -0,0 +1,335 @@
+use crate::walk::{walk_statement, QueryVisitor};
+use rustler::{NifMap, NifUnitEnum};
+use sqlparser::ast::{
+    Assignment, BinaryOperator, Expr, Ident, OnConflictAction, OnInsert, Query, SetExpr, Statement,
+    TableFactor, TableWithJoins, Value,
+};
+use std::collections::HashMap;
+
+/// How a placeholder is used in relation to its column.
+#[derive(Clone, Copy, NifUnitEnum)]
+pub enum ParameterUsage {
+    Compare,
+    Insert,
+    Assign,
+    Other,
+}
+
+/// A placeholder in a prepared statement along with the column it is bound
+/// to, if any. The index is the position of the parameter in a Bind message,
+/// starting at 1.
+#[derive(Clone, NifMap)]
+pub struct ParameterColumn {
+    pub index: usize,
+    pub usage: ParameterUsage,
+    pub schema: Option<String>,
+    pub table: Option<String>,
+    pub column: Option<String>,
+}
+
+/// Find every `$n` or `?` placeholder in a statement and the column it is
+/// compared to, inserted into or assigned to. Placeholders used in any other
+/// way are still reported, with a usage of `Other` and no column.
+pub fn parameter_columns(statement: &Statement) -> Vec<ParameterColumn> {
+    let mut visitor = ParameterVisitor {
+        scopes: vec![],
+        targets: HashMap::new(),
+        positional: 0,
+        parameters: vec![],
+    };
+    walk_statement(statement, &mut visitor);
+    visitor.parameters
+}
+
+struct TableRef {
+    schema: Option<String>,
+    name: String,
+    alias: Option<String>,
+}
+
+struct Target {
+    usage: ParameterUsage,
+    schema: Option<String>,
+    table: Option<String>,
+    column: String,
+}
+
+/// Visitor that records the target of each placeholder when visiting the
+/// expression or statement containing it, then reports the placeholder in
+/// the order it is reached. Targets are keyed by the address of the
+/// placeholder expression, which is stable while the statement is visited.
+struct ParameterVisitor {
+    scopes: Vec<Vec<TableRef>>,
+    targets: HashMap<*const Expr, Target>,
+    positional: usize,
+    parameters: Vec<ParameterColumn>,
+}
+
+impl ParameterVisitor {
+    /// Resolve a column reference to the table it belongs to within the
+    /// current query. Unqualified columns are only resolved when a single
+    /// table is in scope.
+    fn resolve(&self, expr: &Expr, usage: ParameterUsage) -> Option<Target> {
+        let (qualifier, column) = match expr {
+            Expr::Identifier(ident) => (None, ident.value.clone()),
+            Expr::CompoundIdentifier(idents) if idents.len() >= 2 => (
+                Some(idents[idents.len() - 2].value.to_lowercase()),
+                idents[idents.len() - 1].value.clone(),
+            ),
+            _ => return None,
+        };
+
+        let tables: &[TableRef] = match self.scopes.last() {
+            Some(tables) => tables.as_slice(),
+            None => &[],
+        };
+        let table = match qualifier {
+            Some(q) => tables
+                .iter()
+                .find(|t| t.alias.as_ref() == Some(&q) || t.name.to_lowercase() == q),
+            None if tables.len() == 1 => tables.first(),
+            None => None,
+        };
+
+        Some(Target {
+            usage,
+            schema: table.and_then(|t| t.schema.clone()),
+            table: table.map(|t| t.name.clone()),
+            column,
+        })
+    }
+
+    /// Record the target of a placeholder if the expression is one.
+    fn register(&mut self, expr: &Expr, target: Option<Target>) {
+        if let (Some(placeholder), Some(target)) = (placeholder(expr), target) {
+            self.targets.insert(placeholder as *const Expr, target);
+        }
+    }
+
+    fn register_column(&mut self, column: &Expr, value: &Expr, usage: ParameterUsage) {
+        if placeholder(value).is_some() {
+            let target = self.resolve(column, usage);
+            self.register(value, target)
+        }
+    }
+
+    fn register_assignments(&mut self, assignments: &[Assignment]) {
+        for assignment in assignments.iter() {
+            let column = match assignment.id.len() {
+                0 => continue,
+                1 => Expr::Identifier(assignment.id[0].clone()),
+                _ => Expr::CompoundIdentifier(assignment.id.clone()),
+            };
+            self.register_column(&column, &assignment.value, ParameterUsage::Assign)
+        }
+    }
+}
+
+impl QueryVisitor for ParameterVisitor {
+    fn enter_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Insert {
+                table_name,
+                columns,
+                source,
+                on,
+                ..
+            } => {
+                let mut tables = vec![];
+                let idents = &table_name.0;
+                if let Some(name) = idents.last() {
+                    tables.push(TableRef {
+                        schema: schema_name(idents),
+                        name: name.value.clone(),
+                        alias: None,
+                    })
+                }
+                self.scopes.push(tables);
+
+                if let SetExpr::Values(values) = &*source.body {
+                    for row in values.rows.iter() {
+                        for (value, column) in row.iter().zip(columns.iter()) {
+                            let column = Expr::Identifier(column.clone());
+                            self.register_column(&column, value, ParameterUsage::Insert)
+                        }
+                    }
+                }
+
+                match on {
+                    Some(OnInsert::DuplicateKeyUpdate(assignments)) => {
+                        self.register_assignments(assignments)
+                    }
+                    Some(OnInsert::OnConflict(conflict)) => {
+                        if let OnConflictAction::DoUpdate(update) = &conflict.action {
+                            self.register_assignments(&update.assignments)
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            Statement::Update {
+                table,
+                assignments,
+                from,
+                ..
+            } => {
+                let mut tables = vec![];
+                table_refs(table, &mut tables);
+                if let Some(from) = from {
+                    table_refs(from, &mut tables);
+                }
+                self.scopes.push(tables);
+                self.register_assignments(assignments)
+            }
+            Statement::Delete { from, using, .. } => {
+                let mut tables = vec![];
+                for t in from.iter().chain(using.iter().flatten()) {
+                    table_refs(t, &mut tables);
+                }
+                self.scopes.push(tables)
+            }
+            _ => (),
+        }
+    }
+
+    fn exit_statement(&mut self, statement: &Statement) {
+        if let Statement::Insert { .. } | Statement::Update { .. } | Statement::Delete { .. } =
+            statement
+        {
+            self.scopes.pop();
+        }
+    }
+
+    fn enter_query(&mut self, query: &Query) {
+        let mut tables = vec![];
+        set_expr_table_refs(&query.body, &mut tables);
+        self.scopes.push(tables);
+    }
+
+    fn exit_query(&mut self, _query: &Query) {
+        self.scopes.pop();
+    }
+
+    fn enter_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::BinaryOp {
+                left,
+                op:
+                    BinaryOperator::Eq
+                    | BinaryOperator::NotEq
+                    | BinaryOperator::Gt
+                    | BinaryOperator::Lt
+                    | BinaryOperator::GtEq
+                    | BinaryOperator::LtEq,
+                right,
+            } => {
+                self.register_column(left, right, ParameterUsage::Compare);
+                self.register_column(right, left, ParameterUsage::Compare)
+            }
+            Expr::InList { expr, list, .. } => {
+                for item in list.iter() {
+                    self.register_column(expr, item, ParameterUsage::Compare)
+                }
+            }
+            Expr::Between {
+                expr, low, high, ..
+            } => {
+                self.register_column(expr, low, ParameterUsage::Compare);
+                self.register_column(expr, high, ParameterUsage::Compare)
+            }
+            Expr::Like { expr, pattern, .. }
+            | Expr::ILike { expr, pattern, .. }
+            | Expr::SimilarTo { expr, pattern, .. } => {
+                self.register_column(expr, pattern, ParameterUsage::Compare)
+            }
+            Expr::Value(Value::Placeholder(name)) => {
+                let index = if name == "?" {
+                    self.positional += 1;
+                    Some(self.positional)
+                } else {
+                    // `$1` and `?1` are both numbered explicitly
+                    name.get(1..).and_then(|n| n.parse().ok())
+                };
+
+                let target = self.targets.remove(&(expr as *const Expr));
+                if let Some(index) = index {
+                    let parameter = match target {
+                        Some(target) => ParameterColumn {
+                            index,
+                            usage: target.usage,
+                            schema: target.schema,
+                            table: target.table,
+                            column: Some(target.column),
+                        },
+                        None => ParameterColumn {
+                            index,
+                            usage: ParameterUsage::Other,
+                            schema: None,
+                            table: None,
+                            column: None,
+                        },
+                    };
+                    self.parameters.push(parameter)
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Return the placeholder expression, looking through casts such as `$1::text`.
+fn placeholder(expr: &Expr) -> Option<&Expr> {
+    match expr {
+        Expr::Value(Value::Placeholder(_)) => Some(expr),
+        Expr::Nested(expr) | Expr::Cast { expr, .. } => placeholder(expr),
+        _ => None,
+    }
+}
+
+fn schema_name(idents: &[Ident]) -> Option<String> {
+    if idents.len() >= 2 {
+        Some(idents[idents.len() - 2].value.clone())
+    } else {
+        None
+    }
+}
+
+fn table_refs(table: &TableWithJoins, acc: &mut Vec<TableRef>) {
+    table_factor_refs(&table.relation, acc);
+    for join in table.joins.iter() {
+        table_factor_refs(&join.relation, acc)
+    }
+}
+
+fn table_factor_refs(factor: &TableFactor, acc: &mut Vec<TableRef>) {
+    match factor {
+        TableFactor::Table { name, alias, .. } => {
+            if let Some(ident) = name.0.last() {
+                acc.push(TableRef {
+                    schema: schema_name(&name.0),
+                    name: ident.value.clone(),
+                    alias: alias.as_ref().map(|a| a.name.value.to_lowercase()),
+                })
+            }
+        }
+        TableFactor::NestedJoin {
+            table_with_joins, ..
+        } => table_refs(table_with_joins, acc),
+        _ => (),
+    }
+}
+
+fn set_expr_table_refs(body: &SetExpr, acc: &mut Vec<TableRef>) {
+    match body {
+        SetExpr::Select(select) => {
+            for table in select.from.iter() {
+                table_refs(table, acc)
+            }
+        }
+        SetExpr::SetOperation { left, right, .. } => {
+            set_expr_table_refs(left, acc);
+            set_expr_table_refs(right, acc)
+        }
+        _ => (),
+    }
+}
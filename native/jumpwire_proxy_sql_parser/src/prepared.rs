@@ -0,0 +1,3 @@
+mod registry;
+
+pub use self::registry::{PreparedRegistry, PreparedStatement};
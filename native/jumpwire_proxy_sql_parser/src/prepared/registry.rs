@@ -0,0 +1,102 @@
+use crate::encoding::ClientEncoding;
+use crate::parameter::{parameter_columns, ParameterColumn};
+use crate::resource::StatementResource;
+use sqlparser::ast::Statement;
+use std::collections::HashMap;
+use std::sync::{Arc, PoisonError, RwLock};
+
+/// A statement from a Parse message of the extended query protocol, along
+/// with the analysis needed when it is later bound and executed.
+pub struct PreparedStatement {
+    /// Each parsed statement with the text it was parsed from, if known.
+    pub statements: Vec<(Arc<Statement>, Option<String>)>,
+    /// Parameter type OIDs declared by the client. Unspecified types are 0.
+    pub param_types: Vec<u32>,
+    pub parameters: Vec<ParameterColumn>,
+    pub encoding: ClientEncoding,
+}
+
+impl PreparedStatement {
+    pub fn new<'a>(
+        resources: impl IntoIterator<Item = &'a StatementResource>,
+        param_types: Vec<u32>,
+        encoding: ClientEncoding,
+    ) -> Self {
+        let statements: Vec<_> = resources
+            .into_iter()
+            .map(|resource| {
+                let source = resource.source().map(|(sql, _)| sql.to_string());
+                (resource.snapshot(), source)
+            })
+            .collect();
+        let parameters = statements
+            .iter()
+            .flat_map(|(statement, _)| parameter_columns(statement))
+            .collect();
+        PreparedStatement {
+            statements,
+            param_types,
+            parameters,
+            encoding,
+        }
+    }
+
+    /// New resources for the statements, sharing the parsed statements and
+    /// keeping their text so that rewrites can still be spliced into it.
+    pub fn resources(&self) -> Vec<StatementResource> {
+        self.statements
+            .iter()
+            .map(|(statement, source)| {
+                let resource = StatementResource::shared(statement.clone(), self.encoding);
+                match source {
+                    Some(sql) => resource.with_source(sql),
+                    None => resource,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Prepared statements for a single client connection, keyed by statement
+/// name. The unnamed statement uses an empty name and is replaced by every
+/// new Parse message, matching the PostgreSQL protocol.
+///
+/// Statements are shared rather than copied while the lock is held, so the
+/// lock is only ever held briefly and callers simply wait for it.
+pub struct PreparedRegistry {
+    statements: RwLock<HashMap<String, Arc<PreparedStatement>>>,
+}
+
+impl PreparedRegistry {
+    pub fn new() -> Self {
+        PreparedRegistry {
+            statements: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn insert(&self, name: String, statement: PreparedStatement) {
+        let mut statements = self
+            .statements
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        statements.insert(name, Arc::new(statement));
+    }
+
+    /// Return a prepared statement. Statements are shared, so callers should
+    /// only rewrite them through new resources from `resources`.
+    pub fn get(&self, name: &str) -> Option<Arc<PreparedStatement>> {
+        let statements = self
+            .statements
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        statements.get(name).cloned()
+    }
+
+    pub fn remove(&self, name: &str) {
+        let mut statements = self
+            .statements
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        statements.remove(name);
+    }
+}
@@ -0,0 +1,212 @@
+use crate::scan::{ScannedColumn, ScannedRelation};
+use crate::walk::{walk_statement, QueryVisitor};
+use rustler::{NifMap, NifUnitEnum};
+use sqlparser::ast::{Expr, Ident, ObjectName, Query, Statement};
+
+/// Parts of a statement that can be returned without serializing its AST.
+#[derive(Clone, Copy, PartialEq, NifUnitEnum)]
+pub enum ProjectionField {
+    Kind,
+    Tables,
+    Fields,
+}
+
+/// A summary of a statement. Only the requested parts are filled in, the
+/// rest are `nil` in Elixir.
+#[derive(Default, NifMap)]
+pub struct Projection {
+    /// Name of the statement variant, matching the struct name of the full
+    /// term such as `Query` or `Insert`.
+    pub kind: Option<String>,
+    /// Relations referenced anywhere in the statement, excluding CTEs.
+    pub tables: Option<Vec<ScannedRelation>>,
+    /// Columns referenced anywhere in the statement.
+    pub fields: Option<Vec<ScannedColumn>>,
+}
+
+pub fn project(statement: &Statement, fields: &[ProjectionField]) -> Projection {
+    let mut projection = Projection::default();
+    if fields.contains(&ProjectionField::Kind) {
+        projection.kind = Some(statement_kind(statement).to_string());
+    }
+
+    let tables = fields.contains(&ProjectionField::Tables);
+    let columns = fields.contains(&ProjectionField::Fields);
+    if tables || columns {
+        let mut visitor = ProjectionVisitor {
+            ctes: vec![],
+            tables: vec![],
+            fields: vec![],
+        };
+        walk_statement(statement, &mut visitor);
+        if tables {
+            projection.tables = Some(visitor.tables);
+        }
+        if columns {
+            projection.fields = Some(visitor.fields);
+        }
+    }
+    projection
+}
+
+fn statement_kind(statement: &Statement) -> &'static str {
+    match statement {
+        Statement::Query(_) => "Query",
+        Statement::Insert { .. } => "Insert",
+        Statement::Update { .. } => "Update",
+        Statement::Delete { .. } => "Delete",
+        Statement::Truncate { .. } => "Truncate",
+        Statement::Copy { .. } => "Copy",
+        Statement::CreateView { .. } => "CreateView",
+        Statement::CreateTable { .. } => "CreateTable",
+        Statement::CreateIndex { .. } => "CreateIndex",
+        Statement::AlterTable { .. } => "AlterTable",
+        Statement::Drop { .. } => "Drop",
+        Statement::SetVariable { .. } => "SetVariable",
+        Statement::SetTimeZone { .. } => "SetTimeZone",
+        Statement::ShowVariable { .. } => "ShowVariable",
+        Statement::StartTransaction { .. } => "StartTransaction",
+        Statement::Commit { .. } => "Commit",
+        Statement::Rollback { .. } => "Rollback",
+        Statement::Prepare { .. } => "Prepare",
+        Statement::Execute { .. } => "Execute",
+        Statement::Deallocate { .. } => "Deallocate",
+        Statement::Explain { .. } => "Explain",
+        _ => "Other",
+    }
+}
+
+/// Visitor collecting every relation and column reference, each only once.
+struct ProjectionVisitor {
+    /// CTEs of each query being walked, innermost last.
+    ctes: Vec<CteScope>,
+    tables: Vec<ScannedRelation>,
+    fields: Vec<ScannedColumn>,
+}
+
+/// The CTEs defined by the WITH clause of a query. The walker visits their
+/// bodies in order before the rest of the query, so `defined` counts the
+/// bodies already walked. A CTE is only visible to the ones after it and to
+/// the rest of the query, or to its own body as well when recursive.
+struct CteScope {
+    names: Vec<String>,
+    recursive: bool,
+    defined: usize,
+}
+
+impl CteScope {
+    fn contains(&self, name: &str) -> bool {
+        let visible = if self.recursive {
+            self.names.len().min(self.defined + 1)
+        } else {
+            self.defined
+        };
+        self.names[..visible].iter().any(|n| n == name)
+    }
+}
+
+impl ProjectionVisitor {
+    fn push_field(&mut self, qualifier: Option<&Ident>, name: &Ident) {
+        let field = ScannedColumn {
+            qualifier: qualifier.map(|q| q.value.clone()),
+            name: name.value.clone(),
+        };
+        if !self.fields.contains(&field) {
+            self.fields.push(field)
+        }
+    }
+}
+
+impl QueryVisitor for ProjectionVisitor {
+    fn enter_relation(&mut self, relation: &ObjectName) {
+        let idents = &relation.0;
+        let name = match idents.last() {
+            Some(ident) => ident.value.clone(),
+            None => return,
+        };
+        if idents.len() == 1 {
+            let name = name.to_lowercase();
+            if self.ctes.iter().any(|scope| scope.contains(&name)) {
+                return;
+            }
+        }
+
+        let schema = match idents.len() {
+            0 | 1 => None,
+            n => Some(idents[n - 2].value.clone()),
+        };
+        let table = ScannedRelation { schema, name };
+        if !self.tables.contains(&table) {
+            self.tables.push(table)
+        }
+    }
+
+    fn enter_query(&mut self, query: &Query) {
+        let scope = match &query.with {
+            Some(with) => CteScope {
+                names: with
+                    .cte_tables
+                    .iter()
+                    .map(|cte| cte.alias.name.value.to_lowercase())
+                    .collect(),
+                recursive: with.recursive,
+                defined: 0,
+            },
+            None => CteScope {
+                names: vec![],
+                recursive: false,
+                defined: 0,
+            },
+        };
+        self.ctes.push(scope)
+    }
+
+    fn exit_query(&mut self, _query: &Query) {
+        self.ctes.pop();
+        // until all of its CTEs are walked, the only queries directly inside
+        // a query are the CTE bodies
+        if let Some(parent) = self.ctes.last_mut() {
+            if parent.defined < parent.names.len() {
+                parent.defined += 1
+            }
+        }
+    }
+
+    fn enter_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Insert { columns, .. } => {
+                for column in columns.iter() {
+                    self.push_field(None, column)
+                }
+            }
+            Statement::Update { assignments, .. } => {
+                for assignment in assignments.iter() {
+                    if let Some(name) = assignment.id.last() {
+                        let n = assignment.id.len();
+                        let qualifier = if n >= 2 {
+                            assignment.id.get(n - 2)
+                        } else {
+                            None
+                        };
+                        self.push_field(qualifier, name)
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn enter_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Identifier(ident) => self.push_field(None, ident),
+            Expr::CompoundIdentifier(idents) => {
+                if let Some(name) = idents.last() {
+                    let n = idents.len();
+                    let qualifier = if n >= 2 { idents.get(n - 2) } else { None };
+                    self.push_field(qualifier, name)
+                }
+            }
+            _ => (),
+        }
+    }
+}
@@ -0,0 +1,3 @@
+mod splice;
+
+pub use self::splice::splice_sql;
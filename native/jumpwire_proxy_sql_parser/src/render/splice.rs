@@ -0,0 +1,249 @@
+use sqlparser::ast::Statement;
+use sqlparser::dialect::PostgreSqlDialect;
+use sqlparser::tokenizer::{Token, TokenWithLocation, Tokenizer};
+use std::ops::Range;
+
+/// Largest number of cells in the table used to diff the changed middle of
+/// two statements. Larger changes are replaced as a single region.
+const MAX_DIFF_CELLS: usize = 1_000_000;
+
+/// A token along with its byte range in the text it was read from.
+struct Span {
+    token: Token,
+    start: usize,
+    end: usize,
+}
+
+/// A run of tokens of the original statement replaced by a run of tokens of
+/// the rewritten one. Either run may be empty.
+struct Hunk {
+    before: Range<usize>,
+    after: Range<usize>,
+}
+
+/// Convert a rewritten statement back into SQL by splicing only the changed
+/// tokens into the text it was parsed from. Everything else, including
+/// comments, whitespace and the spelling of literals, is kept byte for byte.
+///
+/// The original statement has to render to the same tokens as its source for
+/// the two to be lined up. When it doesn't, such as when the source uses a
+/// form that sqlparser normalizes, the rewritten statement is rendered in
+/// full instead.
+pub fn splice_sql(source: &str, original: &Statement, current: &Statement) -> String {
+    if original == current {
+        return source.to_string();
+    }
+
+    let rendered = current.to_string();
+    splice(source, &original.to_string(), &rendered).unwrap_or(rendered)
+}
+
+fn splice(source: &str, before: &str, after: &str) -> Option<String> {
+    let a = spans(source)?;
+    let b = spans(before)?;
+    let c = spans(after)?;
+    if a.len() != b.len()
+        || !a
+            .iter()
+            .zip(b.iter())
+            .all(|(a, b)| same(&a.token, &b.token))
+    {
+        return None;
+    }
+
+    let mut sql = String::with_capacity(source.len() + after.len() - before.len().min(after.len()));
+    let mut cursor = 0;
+    for hunk in diff(&b, &c) {
+        let text = match hunk.after.is_empty() {
+            true => "",
+            false => &after[c[hunk.after.start].start..c[hunk.after.end - 1].end],
+        };
+
+        let (start, end, replacement) = if !hunk.before.is_empty() && !hunk.after.is_empty() {
+            let start = a[hunk.before.start].start;
+            (start, a[hunk.before.end - 1].end, text.to_string())
+        } else if hunk.after.is_empty() {
+            // remove the whitespace separating the tokens from their neighbours
+            let end = a[hunk.before.end - 1].end;
+            match hunk.before.start {
+                0 => (
+                    0,
+                    a.get(hunk.before.end).map_or(end, |s| s.start),
+                    String::new(),
+                ),
+                i => (a[i - 1].end, end, String::new()),
+            }
+        } else {
+            // an insertion is separated from the preceding token the same way
+            // it is in the rendered statement
+            match hunk.before.start {
+                0 => {
+                    let gap = match c.get(hunk.after.end) {
+                        Some(next) => &after[c[hunk.after.end - 1].end..next.start],
+                        None => "",
+                    };
+                    let start = a.first().map_or(0, |s| s.start);
+                    (start, start, format!("{}{}", text, gap))
+                }
+                i => {
+                    let gap = &after[c[hunk.after.start - 1].end..c[hunk.after.start].start];
+                    let end = a[i - 1].end;
+                    (end, end, format!("{}{}", gap, text))
+                }
+            }
+        };
+
+        sql.push_str(&source[cursor..start]);
+        sql.push_str(&replacement);
+        cursor = end;
+    }
+    sql.push_str(&source[cursor..]);
+    Some(sql)
+}
+
+/// Tokenize SQL into its tokens other than whitespace and comments.
+fn spans(sql: &str) -> Option<Vec<Span>> {
+    let dialect = PostgreSqlDialect {};
+    let tokens = Tokenizer::new(&dialect, sql)
+        .tokenize_with_location()
+        .ok()?;
+    let offsets = byte_offsets(sql, &tokens);
+
+    let spans = tokens
+        .into_iter()
+        .enumerate()
+        .filter(|(_, t)| !matches!(t.token, Token::Whitespace(_) | Token::EOF))
+        .map(|(i, t)| Span {
+            token: t.token,
+            start: offsets[i],
+            end: offsets.get(i + 1).copied().unwrap_or(sql.len()),
+        })
+        .collect();
+    Some(spans)
+}
+
+/// Convert the line and column of each token into a byte offset. Tokens
+/// are in order, so the text only needs to be walked once.
+fn byte_offsets(sql: &str, tokens: &[TokenWithLocation]) -> Vec<usize> {
+    let mut chars = sql.char_indices().peekable();
+    let (mut line, mut column) = (1, 1);
+    let mut offsets = Vec::with_capacity(tokens.len());
+
+    for token in tokens.iter() {
+        let location = &token.location;
+        while (line, column) < (location.line, location.column) {
+            match chars.next() {
+                Some((_, '\n')) => {
+                    line += 1;
+                    column = 1;
+                }
+                Some(_) => column += 1,
+                None => break,
+            }
+        }
+        offsets.push(chars.peek().map_or(sql.len(), |(i, _)| *i));
+    }
+    offsets
+}
+
+/// Compare tokens, ignoring the case of keywords and unquoted identifiers
+/// since sqlparser always renders keywords in upper case.
+fn same(a: &Token, b: &Token) -> bool {
+    match (a, b) {
+        (Token::Word(a), Token::Word(b)) if a.quote_style.is_none() && b.quote_style.is_none() => {
+            a.value.eq_ignore_ascii_case(&b.value)
+        }
+        _ => a == b,
+    }
+}
+
+/// Find the runs of tokens that differ between two statements. Rewrites
+/// usually keep the number of tokens, such as when replacing literals, in
+/// which case tokens are compared by position. Otherwise the longest common
+/// subsequence of the changed middle is kept.
+fn diff(b: &[Span], c: &[Span]) -> Vec<Hunk> {
+    let prefix = b
+        .iter()
+        .zip(c.iter())
+        .take_while(|(b, c)| same(&b.token, &c.token))
+        .count();
+    let suffix = b[prefix..]
+        .iter()
+        .rev()
+        .zip(c[prefix..].iter().rev())
+        .take_while(|(b, c)| same(&b.token, &c.token))
+        .count();
+    let (b_end, c_end) = (b.len() - suffix, c.len() - suffix);
+
+    let mut ops = vec![];
+    if b_end - prefix == c_end - prefix {
+        for i in prefix..b_end {
+            let matched = same(&b[i].token, &c[i].token);
+            ops.push((matched, i..i + 1, i..i + 1));
+        }
+    } else if (b_end - prefix) * (c_end - prefix) <= MAX_DIFF_CELLS {
+        lcs_ops(b, c, prefix..b_end, prefix..c_end, &mut ops);
+    } else {
+        ops.push((false, prefix..b_end, prefix..c_end));
+    }
+
+    // merge consecutive changes into a single hunk
+    let mut hunks: Vec<Hunk> = vec![];
+    let mut last_matched = true;
+    for (matched, before, after) in ops {
+        if matched {
+            last_matched = true;
+            continue;
+        }
+        match hunks.last_mut() {
+            Some(hunk) if !last_matched => {
+                hunk.before.end = before.end;
+                hunk.after.end = after.end;
+            }
+            _ => hunks.push(Hunk { before, after }),
+        }
+        last_matched = false;
+    }
+    hunks
+}
+
+/// Walk the longest common subsequence of two ranges of tokens, recording
+/// each token as matched, removed or inserted.
+fn lcs_ops(
+    b: &[Span],
+    c: &[Span],
+    b_range: Range<usize>,
+    c_range: Range<usize>,
+    ops: &mut Vec<(bool, Range<usize>, Range<usize>)>,
+) {
+    let (n, m) = (b_range.len(), c_range.len());
+    let width = m + 1;
+    // lengths[i * width + j] is the LCS of b[i..] and c[j..] within the ranges
+    let mut lengths = vec![0u32; (n + 1) * width];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i * width + j] =
+                if same(&b[b_range.start + i].token, &c[c_range.start + j].token) {
+                    lengths[(i + 1) * width + j + 1] + 1
+                } else {
+                    lengths[(i + 1) * width + j].max(lengths[i * width + j + 1])
+                };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        let (bi, cj) = (b_range.start + i, c_range.start + j);
+        if i < n && j < m && same(&b[bi].token, &c[cj].token) {
+            ops.push((true, bi..bi + 1, cj..cj + 1));
+            i += 1;
+            j += 1;
+        } else if j == m || (i < n && lengths[(i + 1) * width + j] >= lengths[i * width + j + 1]) {
+            ops.push((false, bi..bi + 1, cj..cj));
+            i += 1;
+        } else {
+            ops.push((false, bi..bi, cj..cj + 1));
+            j += 1;
+        }
+    }
+}
@@ -0,0 +1,230 @@
+use crate::encoding::ClientEncoding;
+use sqlparser::ast::Statement;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, PoisonError, RwLock};
+
+/// Source of version numbers. Numbers are unique across every resource, so a
+/// version found through one resource is never mistaken for a version of
+/// another, such as a transaction and the statement it was started from.
+static VERSIONS: AtomicU64 = AtomicU64::new(0);
+
+fn next_version() -> u64 {
+    VERSIONS.fetch_add(1, Ordering::Relaxed)
+}
+
+/// sqlcommenter tags as key and value pairs, in the order they were added.
+type Tags = Vec<(String, String)>;
+
+/// A parsed statement shared with Elixir.
+///
+/// The statement itself is never mutated in place. Readers take a snapshot of
+/// the current version, which stays valid for as long as they hold it, and
+/// rewrites are applied to a copy that then replaces the current version. The
+/// lock is only held long enough to swap the pointer, so concurrent policies
+/// never wait on each other's work and never fail to acquire it.
+pub struct StatementResource {
+    statement: RwLock<Arc<Statement>>,
+    /// Changed whenever the statement is replaced, so that values found by
+    /// their position in one version are never replaced in another. Only
+    /// changed while the statement lock is held for writing.
+    version: AtomicU64,
+    /// The version of the parent statement a transaction was started from,
+    /// along with the parent's tags at the time.
+    base: Option<(Arc<Statement>, Tags)>,
+    /// Encoding of the original query, used when converting back to SQL.
+    encoding: ClientEncoding,
+    /// Text the statement was parsed from along with the parsed statement,
+    /// used to splice rewrites into the original query.
+    source: Option<(Arc<str>, Arc<Statement>)>,
+    /// sqlcommenter tags appended to the statement when converting to SQL.
+    tags: RwLock<Tags>,
+}
+
+impl StatementResource {
+    /// Create a resource from a statement that may be shared with other
+    /// resources, such as one from the parse cache.
+    pub fn shared(statement: Arc<Statement>, encoding: ClientEncoding) -> Self {
+        StatementResource {
+            statement: RwLock::new(statement),
+            version: AtomicU64::new(next_version()),
+            base: None,
+            encoding,
+            source: None,
+            tags: RwLock::new(vec![]),
+        }
+    }
+
+    /// Keep the text the statement was parsed from. The statement must not
+    /// have been rewritten yet.
+    pub fn with_source(mut self, sql: &str) -> Self {
+        self.source = Some((Arc::from(sql), self.snapshot()));
+        self
+    }
+
+    /// The text the statement was parsed from and the statement as parsed.
+    pub fn source(&self) -> Option<(&str, &Statement)> {
+        self.source
+            .as_ref()
+            .map(|(sql, statement)| (&**sql, &**statement))
+    }
+
+    pub fn encoding(&self) -> ClientEncoding {
+        self.encoding
+    }
+
+    /// Start a transaction on the statement. The returned resource can be
+    /// rewritten like any other statement without the changes being visible
+    /// through this one until they are committed.
+    pub fn begin(&self) -> Self {
+        let base = self.snapshot();
+        let tags = self.tags();
+        StatementResource {
+            statement: RwLock::new(base.clone()),
+            version: AtomicU64::new(next_version()),
+            base: Some((base, tags.clone())),
+            encoding: self.encoding,
+            source: self.source.clone(),
+            tags: RwLock::new(tags),
+        }
+    }
+
+    /// Replace the statement with the current version of a transaction, and
+    /// add the tags of the transaction. This fails if the statement was
+    /// changed after the transaction was started, since those changes would
+    /// otherwise be silently discarded.
+    pub fn commit(&self, transaction: &StatementResource) -> Result<(), &'static str> {
+        let (base, _) = transaction.base.as_ref().ok_or("not_a_transaction")?;
+        let statement = transaction.snapshot();
+
+        let mut current = self
+            .statement
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        if !Arc::ptr_eq(&current, base) {
+            return Err("conflict");
+        }
+        *current = statement;
+        self.version.store(next_version(), Ordering::SeqCst);
+        self.add_tags(transaction.tags());
+        Ok(())
+    }
+
+    /// Discard every rewrite and tag made to a transaction since it was
+    /// started.
+    pub fn rollback(&self) -> Result<(), &'static str> {
+        let (base, tags) = self.base.as_ref().ok_or("not_a_transaction")?;
+        let mut current = self
+            .statement
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        *current = base.clone();
+        self.version.store(next_version(), Ordering::SeqCst);
+        *self.tags.write().unwrap_or_else(PoisonError::into_inner) = tags.clone();
+        Ok(())
+    }
+
+    /// Replace the statement outright, discarding any rewrites made to the
+    /// current version.
+    pub fn replace(&self, statement: Statement) {
+        let mut current = self
+            .statement
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        *current = Arc::new(statement);
+        self.version.store(next_version(), Ordering::SeqCst);
+    }
+
+    /// Add sqlcommenter tags to the statement, replacing any earlier tags
+    /// with the same keys. Tags added to a transaction are only added to its
+    /// parent when the transaction is committed.
+    pub fn add_tags(&self, new_tags: Tags) {
+        let mut tags = self.tags.write().unwrap_or_else(PoisonError::into_inner);
+        for (key, value) in new_tags {
+            tags.retain(|(k, _)| *k != key);
+            tags.push((key, value));
+        }
+    }
+
+    pub fn tags(&self) -> Tags {
+        self.tags
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// The current version of the statement.
+    pub fn snapshot(&self) -> Arc<Statement> {
+        self.versioned().1
+    }
+
+    /// The current version of the statement along with its number.
+    pub fn versioned(&self) -> (u64, Arc<Statement>) {
+        // the lock is never held while running a rewrite, so a poisoned lock
+        // still holds a complete statement
+        let statement = self
+            .statement
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        (self.version.load(Ordering::SeqCst), statement.clone())
+    }
+
+    /// Apply a rewrite to a copy of the statement and make it the current
+    /// version. Nothing is changed when the rewrite returns an error.
+    ///
+    /// If another rewrite is committed while this one is running, it is run
+    /// again against the newer version so that neither change is lost. The
+    /// rewrite must therefore be safe to call more than once.
+    pub fn rewrite<T, E, F>(&self, mut f: F) -> Result<T, E>
+    where
+        F: FnMut(&mut Statement) -> Result<T, E>,
+    {
+        loop {
+            let (version, base) = self.versioned();
+            let mut statement = Statement::clone(&base);
+            let result = f(&mut statement)?;
+
+            if self.swap(version, statement) {
+                return Ok(result);
+            }
+        }
+    }
+
+    /// Apply a rewrite like `rewrite`, but only to the given version of the
+    /// statement. This is for rewrites that refer to nodes by their position
+    /// in that version, such as replacing literal values by index, since the
+    /// same position can hold a different node in another one. Fails with
+    /// `stale` instead of running again if the statement is at any other
+    /// version, before or after the rewrite runs.
+    pub fn rewrite_at<T, F>(&self, version: u64, f: F) -> Result<T, &'static str>
+    where
+        F: FnOnce(&mut Statement) -> Result<T, &'static str>,
+    {
+        let (current, base) = self.versioned();
+        if current != version {
+            return Err("stale");
+        }
+
+        let mut statement = Statement::clone(&base);
+        let result = f(&mut statement)?;
+        if self.swap(version, statement) {
+            Ok(result)
+        } else {
+            Err("stale")
+        }
+    }
+
+    /// Make a statement the current version if the statement is still at the
+    /// version it was rewritten from.
+    fn swap(&self, version: u64, statement: Statement) -> bool {
+        let mut current = self
+            .statement
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        if self.version.load(Ordering::SeqCst) != version {
+            return false;
+        }
+        *current = Arc::new(statement);
+        self.version.store(next_version(), Ordering::SeqCst);
+        true
+    }
+}
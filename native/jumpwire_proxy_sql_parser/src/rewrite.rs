@@ -0,0 +1,20 @@
+mod filter;
+mod limit;
+mod mask;
+mod qualify;
+mod registry;
+mod rule;
+mod scope;
+mod substitute;
+mod view;
+mod walker;
+
+pub use self::filter::FilterRule;
+pub use self::limit::LimitRule;
+pub use self::mask::MaskRule;
+pub use self::qualify::QualifyRule;
+pub use self::registry::{object_name, table_key, RuleRegistry};
+pub use self::rule::RewriteRule;
+pub use self::substitute::SubstituteRule;
+pub use self::view::ViewRule;
+pub use self::walker::apply_rules;
@@ -0,0 +1,165 @@
+use super::RewriteRule;
+use crate::matcher::TableMatch;
+use crate::walk::table_name;
+use sqlparser::ast::{
+    BinaryOperator, CopySource, Expr, GroupByExpr, Ident, ObjectName, Query, Select, SelectItem,
+    SetExpr, Statement, TableFactor, TableWithJoins, WildcardAdditionalOptions,
+};
+
+/// Add an expression to the WHERE clause of every SELECT, UPDATE or DELETE
+/// reading from a table. For example, `SELECT * FROM foo` becomes
+/// `SELECT * FROM foo WHERE id = 'abc'`.
+pub struct FilterRule {
+    table: Vec<String>,
+    clause: Expr,
+    applied: usize,
+}
+
+impl FilterRule {
+    pub fn new(table: Vec<String>, clause: Expr) -> Self {
+        FilterRule {
+            table,
+            clause,
+            applied: 0,
+        }
+    }
+
+    fn add_selection(&mut self, selection: &mut Option<Expr>) {
+        let clause = self.clause.clone();
+        *selection = match selection.take() {
+            None => Some(clause),
+            Some(existing) => Some(Expr::BinaryOp {
+                left: Box::new(existing),
+                op: BinaryOperator::And,
+                right: Box::new(clause),
+            }),
+        };
+        self.applied += 1;
+    }
+}
+
+impl RewriteRule for FilterRule {
+    fn enter_statement(&mut self, statement: &mut Statement) {
+        let table = &self.table;
+        match statement {
+            Statement::Update {
+                table: update_table,
+                from,
+                selection,
+                ..
+            } if update_table.matches(table) || from.matches(table) => {
+                self.add_selection(selection)
+            }
+            Statement::Delete {
+                tables,
+                from,
+                using,
+                selection,
+                ..
+            } if tables.matches(table) || from.matches(table) || using.matches(table) => {
+                self.add_selection(selection)
+            }
+            // `COPY table TO` can't be filtered, so it is turned into the
+            // equivalent `COPY (SELECT ...) TO`, which is then filtered like
+            // any other query
+            Statement::Copy { source, .. } => {
+                if let CopySource::Table {
+                    table_name,
+                    columns,
+                } = source
+                {
+                    if table_name.matches(table) {
+                        let query = copy_query(table_name, columns);
+                        *source = CopySource::Query(Box::new(query));
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // `TABLE name` has no WHERE clause either, so it is turned into
+    // `SELECT * FROM name` before the SELECTs of the query are entered
+    fn enter_query(&mut self, query: &mut Query) {
+        expand_tables(&mut query.body, &self.table)
+    }
+
+    fn enter_select(&mut self, select: &mut Select) {
+        if select.matches(&self.table) {
+            self.add_selection(&mut select.selection)
+        }
+    }
+
+    fn applied(&self) -> usize {
+        self.applied
+    }
+}
+
+/// Replace each `TABLE name` reading from the table, including those in set
+/// operations, with the equivalent `SELECT * FROM name`.
+fn expand_tables(body: &mut SetExpr, table: &[String]) {
+    match body {
+        SetExpr::Table(t) => {
+            if let Some(name) = table_name(t).filter(|name| name.matches(table)) {
+                *body = SetExpr::Select(Box::new(select_from(&name, &[])))
+            }
+        }
+        SetExpr::SetOperation { left, right, .. } => {
+            expand_tables(left, table);
+            expand_tables(right, table)
+        }
+        _ => (),
+    }
+}
+
+/// Build `SELECT columns FROM table` as a query.
+fn copy_query(table_name: &ObjectName, columns: &[Ident]) -> Query {
+    Query {
+        with: None,
+        body: Box::new(SetExpr::Select(Box::new(select_from(table_name, columns)))),
+        order_by: vec![],
+        limit: None,
+        offset: None,
+        fetch: None,
+        locks: vec![],
+    }
+}
+
+/// Build `SELECT columns FROM table`, selecting every column when none are given.
+fn select_from(table_name: &ObjectName, columns: &[Ident]) -> Select {
+    let table = TableWithJoins {
+        relation: TableFactor::Table {
+            name: table_name.clone(),
+            alias: None,
+            args: None,
+            with_hints: vec![],
+            version: None,
+            partitions: vec![],
+        },
+        joins: vec![],
+    };
+    let projection = if columns.is_empty() {
+        vec![SelectItem::Wildcard(WildcardAdditionalOptions::default())]
+    } else {
+        columns
+            .iter()
+            .map(|c| SelectItem::UnnamedExpr(Expr::Identifier(c.clone())))
+            .collect()
+    };
+    Select {
+        distinct: None,
+        top: None,
+        projection,
+        into: None,
+        from: vec![table],
+        lateral_views: vec![],
+        selection: None,
+        group_by: GroupByExpr::Expressions(vec![]),
+        cluster_by: vec![],
+        distribute_by: vec![],
+        sort_by: vec![],
+        having: None,
+        named_window: vec![],
+        qualify: None,
+    }
+}
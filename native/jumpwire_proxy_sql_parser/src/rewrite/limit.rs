@@ -0,0 +1,81 @@
+use super::RewriteRule;
+use sqlparser::ast::{BinaryOperator, Expr, Fetch, Statement, Value};
+
+/// Cap the number of rows a query returns to the client, through its LIMIT or
+/// its FETCH FIRST clause if it has one. Subqueries and the source of an
+/// `INSERT ... SELECT` are left alone, as are other statements returning
+/// rows such as `UPDATE ... RETURNING`.
+pub struct LimitRule {
+    max: u64,
+    applied: usize,
+}
+
+impl LimitRule {
+    pub fn new(max: u64) -> Self {
+        LimitRule { max, applied: 0 }
+    }
+}
+
+impl RewriteRule for LimitRule {
+    fn enter_statement(&mut self, statement: &mut Statement) {
+        if let Statement::Query(query) = statement {
+            let capped = match &mut query.fetch {
+                Some(fetch) => cap_fetch(fetch, self.max),
+                None => cap_limit(&mut query.limit, self.max),
+            };
+            if capped {
+                self.applied += 1;
+            }
+        }
+    }
+
+    fn applied(&self) -> usize {
+        self.applied
+    }
+}
+
+/// Lower a LIMIT to at most `max` rows. Limits that aren't literals, such as
+/// placeholders, are wrapped in a CASE expression so that the cap still
+/// applies once they are bound.
+fn cap_limit(limit: &mut Option<Expr>, max: u64) -> bool {
+    let max_expr = Expr::Value(Value::Number(max.to_string(), false));
+    let capped = match limit {
+        None => max_expr,
+        Some(Expr::Value(Value::Number(n, _))) => match n.parse::<u64>() {
+            Ok(n) if n <= max => return false,
+            _ => max_expr,
+        },
+        Some(expr) => Expr::Case {
+            operand: None,
+            conditions: vec![Expr::BinaryOp {
+                left: Box::new(expr.clone()),
+                op: BinaryOperator::Lt,
+                right: Box::new(max_expr.clone()),
+            }],
+            results: vec![expr.clone()],
+            else_result: Some(Box::new(max_expr)),
+        },
+    };
+    *limit = Some(capped);
+    true
+}
+
+/// Lower a FETCH FIRST in the same way as a LIMIT, since a query can't have
+/// both. Without a count it fetches a single row. WITH TIES and PERCENT are
+/// dropped, since either can return more rows than the count.
+fn cap_fetch(fetch: &mut Fetch, max: u64) -> bool {
+    let ties = std::mem::take(&mut fetch.with_ties);
+    if std::mem::take(&mut fetch.percent) {
+        fetch.quantity = Some(Expr::Value(Value::Number(max.to_string(), false)));
+        return true;
+    }
+
+    match fetch.quantity {
+        None if max >= 1 => ties,
+        None => {
+            fetch.quantity = Some(Expr::Value(Value::Number(max.to_string(), false)));
+            true
+        }
+        Some(_) => cap_limit(&mut fetch.quantity, max) || ties,
+    }
+}
@@ -0,0 +1,146 @@
+use super::RewriteRule;
+use crate::matcher::{column_name, Qualifiers};
+use crate::walk::holds_query;
+use sqlparser::ast::{Expr, Ident, Select, SelectItem, Value, VisitMut, VisitorMut};
+use std::ops::ControlFlow;
+
+/// Select a value in place of a column of a table, including where the
+/// column is only part of a selected expression such as `lower(ssn)`. A bare
+/// column keeps its name as an alias. Columns selected through a wildcard
+/// can't be masked without knowing the table's columns, so the statement is
+/// rejected instead.
+pub struct MaskRule {
+    table: Vec<String>,
+    columns: Vec<String>,
+    value: Value,
+    applied: usize,
+    rejection: Option<String>,
+}
+
+impl MaskRule {
+    pub fn new(table: Vec<String>, column: String, value: Value) -> Self {
+        MaskRule {
+            table,
+            columns: vec![column],
+            value,
+            applied: 0,
+            rejection: None,
+        }
+    }
+
+    fn mask_item(&mut self, item: &mut SelectItem, qualifiers: &[String]) {
+        match item {
+            SelectItem::UnnamedExpr(expr) => {
+                if let Some(alias) = self.column_alias(expr, qualifiers) {
+                    *item = SelectItem::ExprWithAlias {
+                        expr: Expr::Value(self.value.clone()),
+                        alias,
+                    };
+                    self.applied += 1;
+                } else {
+                    self.mask_expr(expr, qualifiers)
+                }
+            }
+            SelectItem::ExprWithAlias { expr, .. } => self.mask_expr(expr, qualifiers),
+            SelectItem::Wildcard(_) if !qualifiers.is_empty() => self.reject(),
+            SelectItem::QualifiedWildcard(name, _) => {
+                let qualifier = name.0.last().map(|ident| ident.value.to_lowercase());
+                if qualifier.is_some_and(|q| qualifiers.contains(&q)) {
+                    self.reject()
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// The name of the result column when the expression is just a masked
+    /// column, possibly in parentheses.
+    fn column_alias(&self, expr: &Expr, qualifiers: &[String]) -> Option<Ident> {
+        match expr {
+            Expr::Nested(expr) => self.column_alias(expr, qualifiers),
+            Expr::Identifier(ident) => {
+                column_name(expr, qualifiers, &self.columns).map(|_| ident.clone())
+            }
+            Expr::CompoundIdentifier(idents) => {
+                column_name(expr, qualifiers, &self.columns).and_then(|_| idents.last().cloned())
+            }
+            _ => None,
+        }
+    }
+
+    /// Replace every reference to a masked column within an expression.
+    /// Subqueries are left to their own SELECTs, where the column may belong
+    /// to another table.
+    fn mask_expr(&mut self, expr: &mut Expr, qualifiers: &[String]) {
+        let mut masker = ColumnMasker {
+            qualifiers,
+            columns: &self.columns,
+            value: &self.value,
+            depth: 0,
+            masked: 0,
+        };
+        let _ = expr.visit(&mut masker);
+        self.applied += masker.masked;
+    }
+
+    fn reject(&mut self) {
+        let table = self.table.join(".");
+        let column = self.columns.join(", ");
+        self.rejection = Some(format!(
+            "{table}.{column} is masked and can't be selected with a wildcard"
+        ));
+    }
+}
+
+impl RewriteRule for MaskRule {
+    fn enter_select(&mut self, select: &mut Select) {
+        let mut qualifiers = vec![];
+        select.qualifiers(&self.table, &mut qualifiers);
+        for item in select.projection.iter_mut() {
+            self.mask_item(item, &qualifiers)
+        }
+    }
+
+    fn applied(&self) -> usize {
+        self.applied
+    }
+
+    fn rejection(&self) -> Option<String> {
+        self.rejection.clone()
+    }
+}
+
+struct ColumnMasker<'a> {
+    qualifiers: &'a [String],
+    columns: &'a [String],
+    value: &'a Value,
+    depth: usize,
+    masked: usize,
+}
+
+impl<'a> VisitorMut for ColumnMasker<'a> {
+    type Break = ();
+
+    fn pre_visit_expr(&mut self, expr: &mut Expr) -> ControlFlow<Self::Break> {
+        if self.depth == 0 {
+            // the value tested by `IN (SELECT ...)` is still part of this query
+            if let Expr::InSubquery { expr, .. } = expr {
+                let _ = expr.visit(self);
+            }
+        }
+        if holds_query(expr) {
+            self.depth += 1
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn post_visit_expr(&mut self, expr: &mut Expr) -> ControlFlow<Self::Break> {
+        if holds_query(expr) {
+            self.depth -= 1
+        } else if self.depth == 0 && column_name(expr, self.qualifiers, self.columns).is_some() {
+            *expr = Expr::Value(self.value.clone());
+            self.masked += 1;
+        }
+        ControlFlow::Continue(())
+    }
+}
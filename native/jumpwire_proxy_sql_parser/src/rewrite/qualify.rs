@@ -0,0 +1,68 @@
+use super::scope::CteScope;
+use super::RewriteRule;
+use sqlparser::ast::{CopySource, Ident, ObjectName, Query, Statement, TableFactor};
+
+/// Qualify every table referenced without a schema, such as turning `users`
+/// into `tenant_42.users`. CTE names, table functions and system tables,
+/// whose names always begin with `pg_`, are left alone.
+pub struct QualifyRule {
+    schema: Vec<Ident>,
+    scope: CteScope,
+    applied: usize,
+}
+
+impl QualifyRule {
+    pub fn new(schema: Vec<Ident>) -> Self {
+        QualifyRule {
+            schema,
+            scope: CteScope::default(),
+            applied: 0,
+        }
+    }
+
+    fn qualify(&mut self, name: &mut ObjectName) {
+        match name.0.as_slice() {
+            [ident] if !ident.value.to_lowercase().starts_with("pg_") => (),
+            _ => return,
+        }
+        if self.scope.contains(name) {
+            return;
+        }
+        name.0.splice(0..0, self.schema.iter().cloned());
+        self.applied += 1;
+    }
+}
+
+impl RewriteRule for QualifyRule {
+    fn enter_statement(&mut self, statement: &mut Statement) {
+        match statement {
+            Statement::Insert { table_name, .. } => self.qualify(table_name),
+            Statement::Copy {
+                source: CopySource::Table { table_name, .. },
+                ..
+            } => self.qualify(table_name),
+            _ => (),
+        }
+    }
+
+    fn enter_query(&mut self, query: &mut Query) {
+        self.scope.enter(query)
+    }
+
+    fn exit_query(&mut self, _query: &mut Query) {
+        self.scope.exit()
+    }
+
+    fn enter_table_factor(&mut self, factor: &mut TableFactor) {
+        if let TableFactor::Table {
+            name, args: None, ..
+        } = factor
+        {
+            self.qualify(name)
+        }
+    }
+
+    fn applied(&self) -> usize {
+        self.applied
+    }
+}
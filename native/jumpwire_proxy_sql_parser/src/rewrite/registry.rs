@@ -0,0 +1,140 @@
+use super::{FilterRule, LimitRule, MaskRule, QualifyRule, RewriteRule, SubstituteRule, ViewRule};
+use crate::catalog::ViewCatalog;
+use crate::term_to_value;
+use rustler::types::tuple::get_tuple;
+use rustler::{Error, NifResult, ResourceArc, Term};
+use sqlparser::ast::{BinaryOperator, Expr, Ident};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+/// Create a rule from the arguments of an op, the elements of its tuple
+/// following the name.
+pub type RuleBuilder = fn(&[Term]) -> NifResult<Box<dyn RewriteRule>>;
+
+static REGISTRY: OnceLock<RuleRegistry> = OnceLock::new();
+
+/// Rules that can be applied from Elixir, keyed by the name of their op.
+/// An op is a tuple of the name as an atom followed by its arguments, such as
+/// `{:limit, 100}`.
+pub struct RuleRegistry {
+    builders: HashMap<&'static str, RuleBuilder>,
+}
+
+impl RuleRegistry {
+    pub fn global() -> &'static RuleRegistry {
+        REGISTRY.get_or_init(|| {
+            let mut registry = RuleRegistry {
+                builders: HashMap::new(),
+            };
+            registry.register("filter", filter);
+            registry.register("mask", mask);
+            registry.register("limit", limit);
+            registry.register("substitute", substitute);
+            registry.register("qualify", qualify);
+            registry.register("expand_views", expand_views);
+            registry
+        })
+    }
+
+    pub fn register(&mut self, name: &'static str, builder: RuleBuilder) {
+        self.builders.insert(name, builder);
+    }
+
+    /// Build the rule for an op. Any failure to decode the op or its
+    /// arguments is an error, which callers report as an invalid op.
+    pub fn build(&self, op: Term) -> NifResult<Box<dyn RewriteRule>> {
+        let elements = get_tuple(op).map_err(|_| Error::Atom("invalid_op"))?;
+        let name = match elements.first() {
+            Some(name) => name.atom_to_string()?,
+            None => return Err(Error::Atom("invalid_op")),
+        };
+        let builder = self
+            .builders
+            .get(name.as_str())
+            .ok_or(Error::Atom("invalid_op"))?;
+        builder(&elements[1..])
+    }
+}
+
+fn table_name(term: Term) -> NifResult<Vec<String>> {
+    Ok(table_key(&term.decode::<String>()?))
+}
+
+/// Split a possibly qualified name such as `tenant_42.users` into its parts.
+pub fn object_name(name: &str) -> Vec<Ident> {
+    name.split('.').map(Ident::new).collect()
+}
+
+/// Split a possibly qualified table name into the lowercased parts that
+/// rules match relations against.
+pub fn table_key(name: &str) -> Vec<String> {
+    name.split('.').map(|part| part.to_lowercase()).collect()
+}
+
+/// `{:filter, table, column, :eq, value}`
+fn filter(args: &[Term]) -> NifResult<Box<dyn RewriteRule>> {
+    let [table, column, op, value] = args else {
+        return Err(Error::Atom("invalid_op"));
+    };
+    let op = match op.atom_to_string()?.as_str() {
+        "eq" => BinaryOperator::Eq,
+        _ => return Err(Error::Atom("unknown_operator")),
+    };
+    let clause = Expr::BinaryOp {
+        left: Box::new(Expr::Identifier(Ident::new(column.decode::<String>()?))),
+        op,
+        right: Box::new(Expr::Value(term_to_value(*value)?)),
+    };
+    Ok(Box::new(FilterRule::new(table_name(*table)?, clause)))
+}
+
+/// `{:mask, table, column, value}`
+fn mask(args: &[Term]) -> NifResult<Box<dyn RewriteRule>> {
+    let [table, column, value] = args else {
+        return Err(Error::Atom("invalid_op"));
+    };
+    let column = column.decode::<String>()?.to_lowercase();
+    let value = term_to_value(*value)?;
+    Ok(Box::new(MaskRule::new(table_name(*table)?, column, value)))
+}
+
+/// `{:limit, max}`
+fn limit(args: &[Term]) -> NifResult<Box<dyn RewriteRule>> {
+    let [max] = args else {
+        return Err(Error::Atom("invalid_op"));
+    };
+    Ok(Box::new(LimitRule::new(max.decode()?)))
+}
+
+/// `{:substitute, table, replacement}`, where the replacement may be
+/// qualified with a schema.
+fn substitute(args: &[Term]) -> NifResult<Box<dyn RewriteRule>> {
+    let [table, replacement] = args else {
+        return Err(Error::Atom("invalid_op"));
+    };
+    let replacement: String = replacement.decode()?;
+    Ok(Box::new(SubstituteRule::new(
+        table_name(*table)?,
+        object_name(&replacement),
+    )))
+}
+
+/// `{:qualify, schema}`
+fn qualify(args: &[Term]) -> NifResult<Box<dyn RewriteRule>> {
+    let [schema] = args else {
+        return Err(Error::Atom("invalid_op"));
+    };
+    let schema: String = schema.decode()?;
+    Ok(Box::new(QualifyRule::new(object_name(&schema))))
+}
+
+/// `{:expand_views, catalog, tables}`
+fn expand_views(args: &[Term]) -> NifResult<Box<dyn RewriteRule>> {
+    let [catalog, tables] = args else {
+        return Err(Error::Atom("invalid_op"));
+    };
+    let catalog: ResourceArc<ViewCatalog> = catalog.decode()?;
+    let tables: Vec<String> = tables.decode()?;
+    let tables: Vec<Vec<String>> = tables.iter().map(|table| table_key(table)).collect();
+    Ok(Box::new(ViewRule::new(catalog.protecting(&tables))))
+}
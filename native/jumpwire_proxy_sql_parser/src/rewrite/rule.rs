@@ -0,0 +1,32 @@
+use sqlparser::ast::{Expr, Query, Select, Statement, TableFactor};
+
+/// A single transformation of a statement. The walker calls each hook when
+/// entering and leaving every node of that type, in the order the nodes
+/// appear in the statement, so a rule only needs to implement the hooks for
+/// the nodes it changes.
+///
+/// Every SELECT is reached, including those of subqueries, CTEs, set
+/// operations and derived tables. Changes made by an enter hook are visited
+/// by the walker, so a rule must not add nodes that it would match again.
+pub trait RewriteRule {
+    fn enter_statement(&mut self, _statement: &mut Statement) {}
+    fn exit_statement(&mut self, _statement: &mut Statement) {}
+    fn enter_query(&mut self, _query: &mut Query) {}
+    fn exit_query(&mut self, _query: &mut Query) {}
+    fn enter_select(&mut self, _select: &mut Select) {}
+    fn exit_select(&mut self, _select: &mut Select) {}
+    fn enter_table_factor(&mut self, _factor: &mut TableFactor) {}
+    fn exit_table_factor(&mut self, _factor: &mut TableFactor) {}
+    fn enter_expr(&mut self, _expr: &mut Expr) {}
+    fn exit_expr(&mut self, _expr: &mut Expr) {}
+
+    /// Number of places the rule has changed the statement.
+    fn applied(&self) -> usize;
+
+    /// Why the statement can't be rewritten safely, such as a masked column
+    /// that is only reachable through a wildcard. The whole rewrite is
+    /// discarded when any rule rejects the statement.
+    fn rejection(&self) -> Option<String> {
+        None
+    }
+}
@@ -0,0 +1,34 @@
+use sqlparser::ast::{ObjectName, Query};
+
+/// Names of the CTEs visible from the query being walked. A table reference
+/// using one of these names reads from the CTE rather than from a table.
+#[derive(Default)]
+pub struct CteScope {
+    names: Vec<Vec<String>>,
+}
+
+impl CteScope {
+    pub fn enter(&mut self, query: &Query) {
+        let names = query
+            .with
+            .iter()
+            .flat_map(|with| with.cte_tables.iter())
+            .map(|cte| cte.alias.name.value.to_lowercase())
+            .collect();
+        self.names.push(names);
+    }
+
+    pub fn exit(&mut self) {
+        self.names.pop();
+    }
+
+    pub fn contains(&self, name: &ObjectName) -> bool {
+        match name.0.as_slice() {
+            [ident] => {
+                let name = ident.value.to_lowercase();
+                self.names.iter().any(|names| names.contains(&name))
+            }
+            _ => false,
+        }
+    }
+}
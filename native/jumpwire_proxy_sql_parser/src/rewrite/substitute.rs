@@ -0,0 +1,125 @@
+use super::scope::CteScope;
+use super::RewriteRule;
+use crate::matcher::TableMatch;
+use sqlparser::ast::{
+    visit_expressions_mut, CopySource, Expr, Ident, ObjectName, Query, Statement, TableAlias,
+    TableFactor,
+};
+use std::ops::ControlFlow;
+
+/// Reference another table in place of this one. When read from or updated,
+/// the original name is kept as an alias so that qualified column references
+/// still resolve. The target of an INSERT can't be aliased, so references in
+/// its ON CONFLICT and RETURNING clauses are qualified with the replacement
+/// instead. CTEs with the same name as the table are left alone.
+pub struct SubstituteRule {
+    table: Vec<String>,
+    replacement: Vec<Ident>,
+    scope: CteScope,
+    applied: usize,
+}
+
+impl SubstituteRule {
+    pub fn new(table: Vec<String>, replacement: Vec<Ident>) -> Self {
+        SubstituteRule {
+            table,
+            replacement,
+            scope: CteScope::default(),
+            applied: 0,
+        }
+    }
+
+    fn substitute(&mut self, name: &mut ObjectName) -> bool {
+        if !name.matches(&self.table) || self.scope.contains(name) {
+            return false;
+        }
+        *name = ObjectName(self.replacement.clone());
+        self.applied += 1;
+        true
+    }
+
+    /// Qualify a column reference with the replacement if it is qualified
+    /// with either the full original name or its last part.
+    fn requalify(&self, original: &ObjectName, expr: &mut Expr) -> ControlFlow<()> {
+        let Expr::CompoundIdentifier(idents) = expr else {
+            return ControlFlow::Continue(());
+        };
+        let Some((column, qualifier)) = idents.split_last() else {
+            return ControlFlow::Continue(());
+        };
+        let qualified = match qualifier {
+            [table] => original
+                .0
+                .last()
+                .is_some_and(|last| same_ident(table, last)),
+            _ => {
+                qualifier.len() == original.0.len()
+                    && qualifier
+                        .iter()
+                        .zip(&original.0)
+                        .all(|(a, b)| same_ident(a, b))
+            }
+        };
+        if qualified {
+            let mut requalified = self.replacement.clone();
+            requalified.push(column.clone());
+            *idents = requalified;
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+impl RewriteRule for SubstituteRule {
+    fn enter_statement(&mut self, statement: &mut Statement) {
+        match statement {
+            Statement::Insert {
+                table_name,
+                on,
+                returning,
+                ..
+            } => {
+                let original = table_name.clone();
+                if self.substitute(table_name) {
+                    let _ = visit_expressions_mut(on, |expr| self.requalify(&original, expr));
+                    let _ =
+                        visit_expressions_mut(returning, |expr| self.requalify(&original, expr));
+                }
+            }
+            Statement::Copy {
+                source: CopySource::Table { table_name, .. },
+                ..
+            } => {
+                self.substitute(table_name);
+            }
+            _ => (),
+        }
+    }
+
+    fn enter_query(&mut self, query: &mut Query) {
+        self.scope.enter(query)
+    }
+
+    fn exit_query(&mut self, _query: &mut Query) {
+        self.scope.exit()
+    }
+
+    fn enter_table_factor(&mut self, factor: &mut TableFactor) {
+        if let TableFactor::Table { name, alias, .. } = factor {
+            let original = name.0.last().cloned();
+            if self.substitute(name) && alias.is_none() {
+                *alias = original.map(|ident| TableAlias {
+                    name: ident,
+                    columns: vec![],
+                });
+            }
+        }
+    }
+
+    fn applied(&self) -> usize {
+        self.applied
+    }
+}
+
+fn same_ident(a: &Ident, b: &Ident) -> bool {
+    a.value.to_lowercase() == b.value.to_lowercase()
+}
@@ -0,0 +1,153 @@
+use super::scope::CteScope;
+use super::RewriteRule;
+use crate::catalog::View;
+use crate::matcher::same_table;
+use sqlparser::ast::{ObjectName, Query, Statement, TableAlias, TableFactor, TableWithJoins};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Replace views with their definitions as derived tables, so that other
+/// rules can reach the tables they read from. The view name is kept as an
+/// alias when there isn't one. The table written to by an UPDATE or DELETE
+/// is left alone, as are views referenced from their own definition.
+pub struct ViewRule {
+    views: HashMap<Vec<String>, Arc<View>>,
+    scope: CteScope,
+    /// Table factors written to by the statement being walked. They are only
+    /// compared by address, to tell them apart from reads of the same view.
+    targets: Vec<*const TableFactor>,
+    /// For each table factor being walked, the view it was expanded from.
+    expanding: Vec<Option<Vec<String>>>,
+    applied: usize,
+}
+
+impl ViewRule {
+    pub fn new(views: HashMap<Vec<String>, Arc<View>>) -> Self {
+        ViewRule {
+            views,
+            scope: CteScope::default(),
+            targets: vec![],
+            expanding: vec![],
+            applied: 0,
+        }
+    }
+
+    /// Find a view by name, where a name without a schema is in `public`.
+    fn view(&self, key: &[String]) -> Option<(Vec<String>, Arc<View>)> {
+        self.views
+            .iter()
+            .find(|(name, _)| same_table(name, key))
+            .map(|(name, view)| (name.clone(), view.clone()))
+    }
+
+    fn expand(&mut self, factor: &mut TableFactor) -> Option<Vec<String>> {
+        if self.targets.contains(&(&*factor as *const TableFactor)) {
+            return None;
+        }
+        let TableFactor::Table {
+            name,
+            alias,
+            args: None,
+            ..
+        } = factor
+        else {
+            return None;
+        };
+        if self.scope.contains(name) {
+            return None;
+        }
+        let (key, view) = self.view(&name_key(name))?;
+        if self.expanding.iter().flatten().any(|n| *n == key) {
+            return None;
+        }
+        let last = name.0.last()?.clone();
+
+        let mut alias = alias.take().unwrap_or(TableAlias {
+            name: last,
+            columns: vec![],
+        });
+        if alias.columns.is_empty() {
+            alias.columns = view.columns.clone();
+        }
+        *factor = TableFactor::Derived {
+            lateral: false,
+            subquery: Box::new(view.query.clone()),
+            alias: Some(alias),
+        };
+        self.applied += 1;
+        Some(key)
+    }
+}
+
+fn name_key(name: &ObjectName) -> Vec<String> {
+    name.0.iter().map(|i| i.value.to_lowercase()).collect()
+}
+
+/// Table factors that a DELETE removes rows from. In the MySQL form the
+/// tables are named before FROM, by name or alias, otherwise they are the
+/// tables of the FROM clause itself.
+fn delete_targets<'a>(tables: &[ObjectName], from: &'a [TableWithJoins]) -> Vec<&'a TableFactor> {
+    if tables.is_empty() {
+        return from.iter().map(|table| &table.relation).collect();
+    }
+    let names: Vec<Vec<String>> = tables.iter().map(name_key).collect();
+    from.iter()
+        .flat_map(|table| {
+            std::iter::once(&table.relation).chain(table.joins.iter().map(|join| &join.relation))
+        })
+        .filter(|factor| match factor {
+            TableFactor::Table { name, alias, .. } => {
+                let key = name_key(name);
+                let alias = alias
+                    .as_ref()
+                    .map(|alias| vec![alias.name.value.to_lowercase()]);
+                names
+                    .iter()
+                    .any(|target| *target == key || Some(target) == alias.as_ref())
+            }
+            _ => false,
+        })
+        .collect()
+}
+
+impl RewriteRule for ViewRule {
+    fn enter_statement(&mut self, statement: &mut Statement) {
+        match statement {
+            Statement::Update { table, .. } => self.targets.push(&table.relation),
+            Statement::Delete { tables, from, .. } => {
+                let targets = delete_targets(tables, from);
+                self.targets.extend(
+                    targets
+                        .into_iter()
+                        .map(|factor| factor as *const TableFactor),
+                );
+            }
+            _ => (),
+        }
+    }
+
+    fn exit_statement(&mut self, _statement: &mut Statement) {
+        self.targets.clear()
+    }
+
+    fn enter_query(&mut self, query: &mut Query) {
+        self.scope.enter(query)
+    }
+
+    fn exit_query(&mut self, _query: &mut Query) {
+        self.scope.exit()
+    }
+
+    fn enter_table_factor(&mut self, factor: &mut TableFactor) {
+        let expanded = self.expand(factor);
+        self.expanding.push(expanded)
+    }
+
+    fn exit_table_factor(&mut self, _factor: &mut TableFactor) {
+        self.expanding.pop();
+    }
+
+    fn applied(&self) -> usize {
+        self.applied
+    }
+}
@@ -0,0 +1,166 @@
+use super::RewriteRule;
+use crate::walk::holds_query;
+use sqlparser::ast::{
+    CopySource, Expr, Query, SetExpr, Statement, TableFactor, VisitMut, VisitorMut,
+};
+use std::ops::ControlFlow;
+
+/// Apply every rule to a statement in a single traversal.
+pub fn apply_rules(statement: &mut Statement, rules: &mut [Box<dyn RewriteRule>]) {
+    let mut walker = RuleWalker { rules, skipped: 0 };
+    walker.walk_statement(statement)
+}
+
+/// Calls the hooks of each rule. sqlparser's visitor doesn't visit queries
+/// or SELECTs, so those are walked by hand in the same way as
+/// `walk_statement` does for read-only visitors: a subquery is walked as soon
+/// as the visitor reaches the expression or table factor holding it, and
+/// the visitor's own descent into it is skipped.
+struct RuleWalker<'a> {
+    rules: &'a mut [Box<dyn RewriteRule>],
+    skipped: usize,
+}
+
+impl<'a> RuleWalker<'a> {
+    fn walk_statement(&mut self, statement: &mut Statement) {
+        for rule in self.rules.iter_mut() {
+            rule.enter_statement(statement)
+        }
+        match statement {
+            Statement::Query(query) => self.walk_query(query),
+            Statement::Insert {
+                source,
+                on,
+                returning,
+                ..
+            } => {
+                self.walk_query(source);
+                let _ = on.visit(self);
+                let _ = returning.visit(self);
+            }
+            Statement::CreateTable {
+                query: Some(query), ..
+            } => self.walk_query(query),
+            Statement::CreateView { query, .. } => self.walk_query(query),
+            Statement::Copy {
+                source: CopySource::Query(query),
+                ..
+            } => self.walk_query(query),
+            Statement::Explain { statement, .. } => self.walk_statement(statement),
+            _ => {
+                let _ = statement.visit(self);
+            }
+        }
+        for rule in self.rules.iter_mut() {
+            rule.exit_statement(statement)
+        }
+    }
+
+    fn walk_query(&mut self, query: &mut Query) {
+        for rule in self.rules.iter_mut() {
+            rule.enter_query(query)
+        }
+        if let Some(with) = &mut query.with {
+            for cte in with.cte_tables.iter_mut() {
+                self.walk_query(&mut cte.query)
+            }
+        }
+        self.walk_body(&mut query.body);
+        let _ = query.order_by.visit(self);
+        let _ = query.limit.visit(self);
+        let _ = query.offset.visit(self);
+        let _ = query.fetch.visit(self);
+        let _ = query.locks.visit(self);
+        for rule in self.rules.iter_mut() {
+            rule.exit_query(query)
+        }
+    }
+
+    fn walk_body(&mut self, body: &mut SetExpr) {
+        match body {
+            SetExpr::Select(select) => {
+                for rule in self.rules.iter_mut() {
+                    rule.enter_select(select)
+                }
+                let _ = select.visit(self);
+                for rule in self.rules.iter_mut() {
+                    rule.exit_select(select)
+                }
+            }
+            SetExpr::Query(query) => self.walk_query(query),
+            SetExpr::SetOperation { left, right, .. } => {
+                self.walk_body(left);
+                self.walk_body(right)
+            }
+            SetExpr::Insert(statement) => self.walk_statement(statement),
+            _ => {
+                let _ = body.visit(self);
+            }
+        }
+    }
+}
+
+impl<'a> VisitorMut for RuleWalker<'a> {
+    type Break = ();
+
+    fn pre_visit_table_factor(&mut self, factor: &mut TableFactor) -> ControlFlow<Self::Break> {
+        if self.skipped == 0 {
+            for rule in self.rules.iter_mut() {
+                rule.enter_table_factor(factor)
+            }
+            if let TableFactor::Derived { subquery, .. } = factor {
+                self.walk_query(subquery)
+            }
+        }
+        if let TableFactor::Derived { .. } = factor {
+            self.skipped += 1
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn post_visit_table_factor(&mut self, factor: &mut TableFactor) -> ControlFlow<Self::Break> {
+        if let TableFactor::Derived { .. } = factor {
+            self.skipped -= 1
+        }
+        if self.skipped == 0 {
+            for rule in self.rules.iter_mut() {
+                rule.exit_table_factor(factor)
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_expr(&mut self, expr: &mut Expr) -> ControlFlow<Self::Break> {
+        if self.skipped == 0 {
+            for rule in self.rules.iter_mut() {
+                rule.enter_expr(expr)
+            }
+            match expr {
+                Expr::InSubquery { expr, subquery, .. } => {
+                    let _ = expr.visit(self);
+                    self.walk_query(subquery)
+                }
+                Expr::Exists { subquery, .. }
+                | Expr::Subquery(subquery)
+                | Expr::ArraySubquery(subquery) => self.walk_query(subquery),
+                _ => (),
+            }
+        }
+        if holds_query(expr) {
+            self.skipped += 1
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn post_visit_expr(&mut self, expr: &mut Expr) -> ControlFlow<Self::Break> {
+        if holds_query(expr) {
+            self.skipped -= 1
+        }
+        if self.skipped == 0 {
+            for rule in self.rules.iter_mut() {
+                rule.exit_expr(expr)
+            }
+        }
+        ControlFlow::Continue(())
+    }
+}
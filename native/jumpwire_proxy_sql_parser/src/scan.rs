@@ -0,0 +1,3 @@
+mod tokens;
+
+pub use self::tokens::{scan_tokens, ScanResult, ScannedColumn, ScannedRelation};
@@ -0,0 +1,451 @@
+use rustler::{NifMap, NifUnitEnum};
+use sqlparser::keywords::Keyword;
+use sqlparser::tokenizer::{Token, Word};
+
+/// Keywords that can follow a relation keyword without naming a relation,
+/// such as `FOR UPDATE` or `DO UPDATE SET`.
+const CLAUSE_KEYWORDS: &[Keyword] = &[
+    Keyword::SELECT,
+    Keyword::SET,
+    Keyword::WHERE,
+    Keyword::VALUES,
+    Keyword::DEFAULT,
+    Keyword::ON,
+    Keyword::OF,
+    Keyword::NOWAIT,
+    Keyword::SKIP,
+    Keyword::RETURNING,
+    Keyword::ORDER,
+    Keyword::GROUP,
+    Keyword::HAVING,
+    Keyword::LIMIT,
+    Keyword::OFFSET,
+    Keyword::FETCH,
+    Keyword::FOR,
+    Keyword::UNION,
+    Keyword::EXCEPT,
+    Keyword::INTERSECT,
+];
+
+/// How much the relations found by a scan can be relied on. `Low` means part
+/// of the query couldn't be understood well enough to be sure that every
+/// referenced relation was found.
+#[derive(Clone, Copy, PartialEq, NifUnitEnum)]
+pub enum Confidence {
+    High,
+    Low,
+}
+
+#[derive(PartialEq, NifMap)]
+pub struct ScannedRelation {
+    pub schema: Option<String>,
+    pub name: String,
+}
+
+/// A column reference. The qualifier is the table name or alias it was
+/// qualified with, if any.
+#[derive(PartialEq, NifMap)]
+pub struct ScannedColumn {
+    pub qualifier: Option<String>,
+    pub name: String,
+}
+
+#[derive(NifMap)]
+pub struct ScanResult {
+    pub relations: Vec<ScannedRelation>,
+    pub columns: Vec<ScannedColumn>,
+    pub confidence: Confidence,
+}
+
+/// Find the relations and columns referenced by a query that couldn't be
+/// parsed, using only its tokens.
+///
+/// Relations are the names following keywords such as FROM, JOIN, INTO and
+/// UPDATE. Every other identifier that isn't a keyword, function, alias or
+/// type is reported as a column, so columns named after keywords are missed.
+pub fn scan_tokens(tokens: Vec<Token>) -> ScanResult {
+    let tokens: Vec<Token> = tokens
+        .into_iter()
+        .filter(|t| !matches!(t, Token::Whitespace(_)))
+        .collect();
+
+    let mut scanner = Scanner {
+        tokens,
+        position: 0,
+        parens: vec![],
+        ctes: vec![],
+        result: ScanResult {
+            relations: vec![],
+            columns: vec![],
+            confidence: Confidence::High,
+        },
+    };
+    scanner.scan();
+    scanner.result
+}
+
+/// What an open parenthesis holds.
+#[derive(PartialEq)]
+enum Paren {
+    /// Function arguments, since `FROM` is also used by functions such as
+    /// EXTRACT.
+    Function,
+    Query,
+    /// A subquery or nested join in a FROM list, which can be followed by
+    /// more relations.
+    FromItem,
+    /// The body of a common table expression, along with its name if it only
+    /// becomes visible once the body is closed.
+    Cte(Option<String>),
+}
+
+/// The names defined by a WITH clause. They are visible from the point they
+/// are defined until the end of the query the clause belongs to, which is
+/// closed along with the parenthesis it was opened in.
+struct CteScope {
+    depth: usize,
+    recursive: bool,
+    names: Vec<String>,
+}
+
+struct Scanner {
+    tokens: Vec<Token>,
+    position: usize,
+    parens: Vec<Paren>,
+    ctes: Vec<CteScope>,
+    result: ScanResult,
+}
+
+impl Scanner {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn previous(&self) -> Option<&Token> {
+        self.position
+            .checked_sub(1)
+            .and_then(|i| self.tokens.get(i))
+    }
+
+    fn scan(&mut self) {
+        while let Some(token) = self.peek().cloned() {
+            match token {
+                Token::Word(word) => self.scan_word(word),
+                // function bodies and DO blocks can reference anything
+                Token::DollarQuotedString(_) => {
+                    self.result.confidence = Confidence::Low;
+                    self.position += 1
+                }
+                Token::LParen => {
+                    let is_function = matches!(self.previous(), Some(Token::Word(_)))
+                        && !matches!(
+                            self.tokens.get(self.position + 1),
+                            Some(Token::Word(Word {
+                                keyword: Keyword::SELECT | Keyword::WITH | Keyword::VALUES,
+                                ..
+                            }))
+                        );
+                    let paren = if is_function {
+                        Paren::Function
+                    } else {
+                        Paren::Query
+                    };
+                    self.parens.push(paren);
+                    self.position += 1
+                }
+                Token::RParen => {
+                    self.position += 1;
+                    self.close_paren()
+                }
+                Token::SemiColon => {
+                    self.parens.clear();
+                    self.ctes.clear();
+                    self.position += 1
+                }
+                _ => self.position += 1,
+            }
+        }
+    }
+
+    fn scan_word(&mut self, word: Word) {
+        let in_function = self.parens.last() == Some(&Paren::Function);
+        let after_distinct = matches!(
+            self.previous(),
+            Some(Token::Word(Word {
+                keyword: Keyword::DISTINCT,
+                ..
+            }))
+        );
+
+        match word.keyword {
+            // `IS DISTINCT FROM` compares values rather than naming a relation
+            Keyword::FROM if in_function || after_distinct => self.position += 1,
+            Keyword::FROM | Keyword::JOIN | Keyword::USING => {
+                self.position += 1;
+                self.scan_relations(true)
+            }
+            Keyword::INTO | Keyword::UPDATE | Keyword::TABLE | Keyword::TRUNCATE => {
+                self.position += 1;
+                self.scan_relations(false)
+            }
+            Keyword::WITH => {
+                self.position += 1;
+                let recursive = self.is_keyword(self.position, Keyword::RECURSIVE);
+                if recursive {
+                    self.position += 1;
+                }
+                self.ctes.push(CteScope {
+                    depth: self.parens.len(),
+                    recursive,
+                    names: vec![],
+                });
+                self.scan_cte()
+            }
+            // dynamic SQL hides the statements it runs
+            Keyword::EXECUTE | Keyword::CALL => {
+                self.result.confidence = Confidence::Low;
+                self.position += 1
+            }
+            _ if is_identifier(&word) => self.scan_column(),
+            _ => self.position += 1,
+        }
+    }
+
+    /// Read the relations following a keyword. FROM lists can contain several
+    /// relations separated by commas, and each can have an alias.
+    fn scan_relations(&mut self, list: bool) {
+        loop {
+            while let Some(Token::Word(w)) = self.peek() {
+                if let Keyword::ONLY
+                | Keyword::LATERAL
+                | Keyword::TABLE
+                | Keyword::IF
+                | Keyword::NOT
+                | Keyword::EXISTS = w.keyword
+                {
+                    self.position += 1
+                } else {
+                    break;
+                }
+            }
+
+            match self.peek() {
+                Some(Token::Word(w)) if !CLAUSE_KEYWORDS.contains(&w.keyword) => {
+                    let mut name = self.read_name();
+                    if list && self.peek() == Some(&Token::LParen) {
+                        // a function in a FROM clause, such as generate_series(...)
+                        self.result.confidence = Confidence::Low;
+                        return;
+                    }
+
+                    let relation = ScannedRelation {
+                        name: name.pop().unwrap_or_default(),
+                        schema: name.pop(),
+                    };
+                    let is_cte = relation.schema.is_none() && self.is_cte(&relation.name);
+                    if !is_cte && !self.result.relations.contains(&relation) {
+                        self.result.relations.push(relation)
+                    }
+                    self.skip_alias();
+                }
+                Some(Token::LParen) if list => {
+                    self.position += 1;
+                    self.parens.push(Paren::FromItem);
+                    // subqueries are scanned as usual
+                    if !matches!(
+                        self.peek(),
+                        Some(Token::Word(Word {
+                            keyword: Keyword::SELECT | Keyword::WITH | Keyword::VALUES,
+                            ..
+                        }))
+                    ) {
+                        self.scan_relations(true)
+                    }
+                    return;
+                }
+                Some(Token::LParen) => return,
+                _ if list => {
+                    self.result.confidence = Confidence::Low;
+                    return;
+                }
+                _ => return,
+            }
+
+            if list && self.peek() == Some(&Token::Comma) {
+                self.position += 1
+            } else {
+                return;
+            }
+        }
+    }
+
+    fn skip_alias(&mut self) {
+        if let Some(Token::Word(w)) = self.peek() {
+            if w.keyword == Keyword::AS {
+                self.position += 1;
+            }
+        }
+        if let Some(Token::Word(w)) = self.peek() {
+            if is_identifier(w) {
+                self.position += 1;
+                // column aliases, as in `AS t(a, b)`
+                if let Some(end) = self.column_list_end(self.position) {
+                    self.position = end
+                }
+            }
+        }
+    }
+
+    /// The position after a parenthesized list of column names starting at
+    /// the given position, if there is one.
+    fn column_list_end(&self, position: usize) -> Option<usize> {
+        if self.tokens.get(position) != Some(&Token::LParen) {
+            return None;
+        }
+        let list = &self.tokens[position + 1..];
+        let end = list.iter().position(|t| *t == Token::RParen)?;
+        list[..end]
+            .iter()
+            .all(is_column_list_token)
+            .then_some(position + end + 2)
+    }
+
+    fn scan_column(&mut self) {
+        let skip = matches!(
+            self.previous(),
+            Some(Token::DoubleColon)
+                | Some(Token::Word(Word {
+                    keyword: Keyword::AS,
+                    ..
+                }))
+        );
+        let mut name = self.read_name();
+        let is_function = self.peek() == Some(&Token::LParen);
+        let is_cte = name.len() == 1 && self.is_cte(&name[0]);
+        if skip || is_function || is_cte {
+            return;
+        }
+
+        let column = ScannedColumn {
+            name: name.pop().unwrap_or_default(),
+            qualifier: name.pop(),
+        };
+        if !self.result.columns.contains(&column) {
+            self.result.columns.push(column)
+        }
+    }
+
+    /// Read the start of a common table expression up to the parenthesis
+    /// opening its body, which is then scanned as usual. The name is only
+    /// visible within its own body when the WITH clause is recursive. Nothing
+    /// is read unless the tokens match, as WITH also starts clauses such as
+    /// `WITH ORDINALITY`.
+    fn scan_cte(&mut self) {
+        let name = match self.peek() {
+            Some(Token::Word(word)) => normalize(word),
+            _ => return,
+        };
+        let mut rest = self.position + 1;
+        if let Some(end) = self.column_list_end(rest) {
+            rest = end
+        }
+        if !self.is_keyword(rest, Keyword::AS) {
+            return;
+        }
+        rest += 1;
+        if self.is_keyword(rest, Keyword::NOT) {
+            rest += 1;
+        }
+        if self.is_keyword(rest, Keyword::MATERIALIZED) {
+            rest += 1;
+        }
+        if self.tokens.get(rest) != Some(&Token::LParen) {
+            return;
+        }
+
+        let recursive = self.ctes.last().is_some_and(|scope| scope.recursive);
+        let pending = if recursive {
+            self.define_cte(name);
+            None
+        } else {
+            Some(name)
+        };
+        self.parens.push(Paren::Cte(pending));
+        self.position = rest + 1;
+    }
+
+    fn close_paren(&mut self) {
+        let paren = self.parens.pop();
+        let depth = self.parens.len();
+        while self.ctes.last().is_some_and(|scope| scope.depth > depth) {
+            self.ctes.pop();
+        }
+
+        match paren {
+            Some(Paren::Cte(name)) => {
+                if let Some(name) = name {
+                    self.define_cte(name);
+                }
+                // the next expression in the same WITH list
+                if self.peek() == Some(&Token::Comma) {
+                    self.position += 1;
+                    self.scan_cte();
+                }
+            }
+            Some(Paren::FromItem) => {
+                self.skip_alias();
+                if self.peek() == Some(&Token::Comma) {
+                    self.position += 1;
+                    self.scan_relations(true);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn define_cte(&mut self, name: String) {
+        if let Some(scope) = self.ctes.last_mut() {
+            scope.names.push(name)
+        }
+    }
+
+    fn is_cte(&self, name: &String) -> bool {
+        self.ctes.iter().any(|scope| scope.names.contains(name))
+    }
+
+    fn is_keyword(&self, position: usize, keyword: Keyword) -> bool {
+        matches!(self.tokens.get(position), Some(Token::Word(w)) if w.keyword == keyword)
+    }
+
+    /// Read a possibly qualified name such as `public.users`, returning its
+    /// normalized parts.
+    fn read_name(&mut self) -> Vec<String> {
+        let mut parts = vec![];
+        while let Some(Token::Word(word)) = self.peek() {
+            parts.push(normalize(word));
+            self.position += 1;
+            if self.peek() == Some(&Token::Period) {
+                self.position += 1
+            } else {
+                break;
+            }
+        }
+        parts
+    }
+}
+
+fn is_column_list_token(token: &Token) -> bool {
+    matches!(token, Token::Word(_) | Token::Comma)
+}
+
+fn is_identifier(word: &Word) -> bool {
+    word.quote_style.is_some() || word.keyword == Keyword::NoKeyword
+}
+
+/// Unquoted identifiers are case insensitive in PostgreSQL.
+fn normalize(word: &Word) -> String {
+    match word.quote_style {
+        Some(_) => word.value.clone(),
+        None => word.value.to_lowercase(),
+    }
+}
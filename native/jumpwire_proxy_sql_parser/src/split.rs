@@ -0,0 +1,3 @@
+mod statements;
+
+pub use self::statements::split_statements;
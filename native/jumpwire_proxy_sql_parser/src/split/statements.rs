@@ -0,0 +1,167 @@
+/// Split a query containing several statements into the byte range of each
+/// statement, without parsing them. Ranges are returned as `(start, length)`
+/// and exclude the terminating semicolon and surrounding whitespace.
+/// Statements made up only of comments are skipped.
+///
+/// Semicolons are ignored inside quoted strings and identifiers, dollar
+/// quoted strings and comments, following the rules of the PostgreSQL lexer:
+///
+/// - `''` escapes a quote in a string, as does `\'` in an E-string or in any
+///   string when `standard_conforming_strings` is off
+/// - a dollar quote such as `$body$` can only start where an identifier or
+///   parameter couldn't continue, and ends at the same tag
+/// - block comments can be nested
+///
+/// The query is scanned as bytes, which is safe for UTF-8 and any other
+/// encoding where bytes below 0x80 are always ASCII characters.
+pub fn split_statements(query: &[u8], standard_conforming_strings: bool) -> Vec<(usize, usize)> {
+    let mut ranges = vec![];
+    let mut start = None;
+    let mut has_content = false;
+    let mut end = 0;
+    let mut i = 0;
+
+    while i < query.len() {
+        let c = query[i];
+        let next = query.get(i + 1).copied();
+        let token_start = i;
+
+        i = match c {
+            b';' => {
+                if let (Some(start), true) = (start, has_content) {
+                    ranges.push((start, end - start));
+                }
+                start = None;
+                has_content = false;
+                i += 1;
+                continue;
+            }
+            _ if c.is_ascii_whitespace() => {
+                i += 1;
+                continue;
+            }
+            b'-' if next == Some(b'-') => skip_line_comment(query, i),
+            b'/' if next == Some(b'*') => skip_block_comment(query, i),
+            b'\'' => {
+                has_content = true;
+                let escapes = !standard_conforming_strings || is_escape_string(query, i);
+                skip_quoted(query, i, b'\'', escapes)
+            }
+            b'"' => {
+                has_content = true;
+                skip_quoted(query, i, b'"', false)
+            }
+            b'$' if !follows_identifier(query, i) => {
+                has_content = true;
+                match dollar_tag(query, i) {
+                    Some(tag) => skip_dollar_quoted(query, i, tag),
+                    None => i + 1,
+                }
+            }
+            _ => {
+                has_content = true;
+                i + 1
+            }
+        };
+
+        start.get_or_insert(token_start);
+        end = i;
+    }
+
+    if let (Some(start), true) = (start, has_content) {
+        ranges.push((start, end - start));
+    }
+    ranges
+}
+
+fn skip_line_comment(query: &[u8], i: usize) -> usize {
+    match query[i..].iter().position(|c| *c == b'\n') {
+        Some(offset) => i + offset,
+        None => query.len(),
+    }
+}
+
+fn skip_block_comment(query: &[u8], mut i: usize) -> usize {
+    let mut depth = 0;
+    while i < query.len() {
+        match (query[i], query.get(i + 1)) {
+            (b'/', Some(b'*')) => {
+                depth += 1;
+                i += 2;
+            }
+            (b'*', Some(b'/')) => {
+                depth -= 1;
+                i += 2;
+                if depth == 0 {
+                    return i;
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    query.len()
+}
+
+/// Skip a string or quoted identifier, where a doubled quote is an escaped
+/// quote. With `escapes`, a backslash also escapes the following byte.
+fn skip_quoted(query: &[u8], mut i: usize, quote: u8, escapes: bool) -> usize {
+    i += 1;
+    while i < query.len() {
+        let c = query[i];
+        if escapes && c == b'\\' {
+            i += 2;
+        } else if c == quote {
+            if query.get(i + 1) == Some(&quote) {
+                i += 2;
+            } else {
+                return i + 1;
+            }
+        } else {
+            i += 1;
+        }
+    }
+    query.len()
+}
+
+fn is_identifier_byte(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_' || c == b'$' || c >= 0x80
+}
+
+/// Whether the byte at `i` continues an identifier or number, such as the
+/// `$` in `a$b`.
+fn follows_identifier(query: &[u8], i: usize) -> bool {
+    i > 0 && is_identifier_byte(query[i - 1])
+}
+
+/// Whether the quote at `i` starts an E-string such as `E'\n'`.
+fn is_escape_string(query: &[u8], i: usize) -> bool {
+    i > 0 && matches!(query[i - 1], b'e' | b'E') && !(i > 1 && is_identifier_byte(query[i - 2]))
+}
+
+/// Return the full tag of a dollar quote starting at `i`, such as `$body$`.
+/// `$1` is a parameter rather than a tag.
+fn dollar_tag(query: &[u8], i: usize) -> Option<&[u8]> {
+    let rest = &query[i + 1..];
+    if let Some(c) = rest.first() {
+        if c.is_ascii_digit() {
+            return None;
+        }
+    }
+
+    let length = rest
+        .iter()
+        .position(|c| !is_identifier_byte(*c) || *c == b'$')?;
+    if rest.get(length) == Some(&b'$') {
+        Some(&query[i..i + length + 2])
+    } else {
+        None
+    }
+}
+
+fn skip_dollar_quoted(query: &[u8], i: usize, tag: &[u8]) -> usize {
+    let body = i + tag.len();
+    match query[body..].windows(tag.len()).position(|w| w == tag) {
+        Some(offset) => body + offset + tag.len(),
+        None => query.len(),
+    }
+}
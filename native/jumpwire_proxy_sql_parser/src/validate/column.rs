@@ -0,0 +1,276 @@
+use crate::matcher::{column_name, Qualifiers};
+use crate::walk::{walk_statement, QueryVisitor};
+use rustler::NifUnitEnum;
+use sqlparser::ast::{
+    visit_expressions, BinaryOperator, Expr, FunctionArg, FunctionArgExpr, JoinConstraint,
+    JoinOperator, OrderByExpr, Query, Select, SetExpr, Statement, UnaryOperator, WindowSpec,
+    WindowType,
+};
+use std::ops::ControlFlow;
+
+/// Operations that can't be performed on encrypted or tokenized columns. The
+/// database would compare or compute over the stored ciphertext instead of
+/// the original values, silently returning the wrong results.
+#[derive(Clone, Copy, PartialEq, NifUnitEnum)]
+pub enum ViolationKind {
+    OrderBy,
+    Comparison,
+    Like,
+    Arithmetic,
+    Aggregate,
+    Join,
+}
+
+impl ViolationKind {
+    fn description(&self) -> &'static str {
+        match self {
+            ViolationKind::OrderBy => "ORDER BY",
+            ViolationKind::Comparison => "range comparisons",
+            ViolationKind::Like => "pattern matching",
+            ViolationKind::Arithmetic => "arithmetic",
+            ViolationKind::Aggregate => "aggregate functions",
+            ViolationKind::Join => "joins",
+        }
+    }
+}
+
+#[derive(PartialEq)]
+pub struct Violation {
+    pub kind: ViolationKind,
+    pub column: String,
+}
+
+impl Violation {
+    /// A human readable message suitable for returning to a SQL client.
+    pub fn message(&self) -> String {
+        format!(
+            "{} cannot be used on protected column \"{}\"",
+            self.kind.description(),
+            self.column
+        )
+    }
+}
+
+/// Find every use of the given columns of a table that can't be performed
+/// against protected data. Each violation is only reported once per column.
+pub fn column_violations(
+    statement: &Statement,
+    table: &[String],
+    columns: &[String],
+) -> Vec<Violation> {
+    let mut validator = ColumnValidator {
+        table,
+        columns,
+        scopes: vec![],
+        violations: vec![],
+    };
+    walk_statement(statement, &mut validator);
+    validator.violations
+}
+
+/// Visitor tracking the qualifiers of the matching table for each nested
+/// query, so that a column is only attributed to the table when the query
+/// it appears in actually references that table.
+struct ColumnValidator<'a> {
+    table: &'a [String],
+    columns: &'a [String],
+    scopes: Vec<Vec<String>>,
+    violations: Vec<Violation>,
+}
+
+impl<'a> ColumnValidator<'a> {
+    fn check(&mut self, kind: ViolationKind, expr: &Expr) {
+        let qualifiers = match self.scopes.last() {
+            Some(qualifiers) => qualifiers,
+            None => return,
+        };
+
+        if let Some(column) = column_name(unwrap_expr(expr), qualifiers, self.columns) {
+            let violation = Violation { kind, column };
+            if !self.violations.contains(&violation) {
+                self.violations.push(violation)
+            }
+        }
+    }
+
+    fn check_order_by(&mut self, order_by: &[OrderByExpr]) {
+        for order in order_by.iter() {
+            self.check(ViolationKind::OrderBy, &order.expr)
+        }
+    }
+
+    /// Rows are only ordered within a window. Partitioning compares values
+    /// for equality, which works the same on deterministic ciphertext or
+    /// tokens as on the original values.
+    fn check_window(&mut self, spec: &WindowSpec) {
+        self.check_order_by(&spec.order_by)
+    }
+
+    fn check_joins(&mut self, body: &SetExpr) {
+        match body {
+            SetExpr::Select(select) => {
+                for join in select.from.iter().flat_map(|t| t.joins.iter()) {
+                    let constraint = match join.join_operator {
+                        JoinOperator::Inner(ref c)
+                        | JoinOperator::LeftOuter(ref c)
+                        | JoinOperator::RightOuter(ref c)
+                        | JoinOperator::FullOuter(ref c)
+                        | JoinOperator::LeftSemi(ref c)
+                        | JoinOperator::RightSemi(ref c)
+                        | JoinOperator::LeftAnti(ref c)
+                        | JoinOperator::RightAnti(ref c) => c,
+                        _ => continue,
+                    };
+
+                    match constraint {
+                        JoinConstraint::On(expr) => {
+                            let _ = visit_expressions(expr, |e| {
+                                self.check(ViolationKind::Join, e);
+                                ControlFlow::<()>::Continue(())
+                            });
+                        }
+                        JoinConstraint::Using(idents) => {
+                            for ident in idents.iter() {
+                                self.check(ViolationKind::Join, &Expr::Identifier(ident.clone()))
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            SetExpr::SetOperation { left, right, .. } => {
+                self.check_joins(left);
+                self.check_joins(right)
+            }
+            _ => (),
+        }
+    }
+}
+
+impl<'a> QueryVisitor for ColumnValidator<'a> {
+    fn enter_statement(&mut self, statement: &Statement) {
+        let mut qualifiers = vec![];
+        match statement {
+            Statement::Update { table, from, .. } => {
+                table.qualifiers(self.table, &mut qualifiers);
+                from.qualifiers(self.table, &mut qualifiers);
+            }
+            Statement::Delete { from, using, .. } => {
+                from.qualifiers(self.table, &mut qualifiers);
+                using.qualifiers(self.table, &mut qualifiers);
+            }
+            _ => return,
+        }
+        self.scopes.push(qualifiers);
+    }
+
+    fn exit_statement(&mut self, statement: &Statement) {
+        if let Statement::Update { .. } | Statement::Delete { .. } = statement {
+            self.scopes.pop();
+        }
+    }
+
+    fn enter_query(&mut self, query: &Query) {
+        let mut qualifiers = vec![];
+        query.qualifiers(self.table, &mut qualifiers);
+        self.scopes.push(qualifiers);
+
+        self.check_order_by(&query.order_by);
+        self.check_joins(&query.body);
+    }
+
+    fn exit_query(&mut self, _query: &Query) {
+        self.scopes.pop();
+    }
+
+    fn enter_select(&mut self, select: &Select) {
+        for window in select.named_window.iter() {
+            self.check_window(&window.1)
+        }
+    }
+
+    fn enter_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::BinaryOp { left, op, right } => {
+                let kind = match op {
+                    BinaryOperator::Gt
+                    | BinaryOperator::Lt
+                    | BinaryOperator::GtEq
+                    | BinaryOperator::LtEq => ViolationKind::Comparison,
+                    BinaryOperator::Plus
+                    | BinaryOperator::Minus
+                    | BinaryOperator::Multiply
+                    | BinaryOperator::Divide
+                    | BinaryOperator::Modulo => ViolationKind::Arithmetic,
+                    BinaryOperator::PGRegexMatch
+                    | BinaryOperator::PGRegexIMatch
+                    | BinaryOperator::PGRegexNotMatch
+                    | BinaryOperator::PGRegexNotIMatch => ViolationKind::Like,
+                    _ => return,
+                };
+                self.check(kind, left);
+                self.check(kind, right)
+            }
+            Expr::UnaryOp {
+                op: UnaryOperator::Plus | UnaryOperator::Minus,
+                expr,
+            } => self.check(ViolationKind::Arithmetic, expr),
+            Expr::Between {
+                expr, low, high, ..
+            } => {
+                self.check(ViolationKind::Comparison, expr);
+                self.check(ViolationKind::Comparison, low);
+                self.check(ViolationKind::Comparison, high)
+            }
+            Expr::Like { expr, pattern, .. }
+            | Expr::ILike { expr, pattern, .. }
+            | Expr::SimilarTo { expr, pattern, .. } => {
+                self.check(ViolationKind::Like, expr);
+                self.check(ViolationKind::Like, pattern)
+            }
+            Expr::ArrayAgg(agg) => {
+                if let Some(order_by) = &agg.order_by {
+                    self.check_order_by(order_by)
+                }
+            }
+            Expr::ListAgg(agg) => self.check_order_by(&agg.within_group),
+            Expr::Function(func) => {
+                self.check_order_by(&func.order_by);
+                if let Some(WindowType::WindowSpec(spec)) = &func.over {
+                    self.check_window(spec)
+                }
+
+                let name = match func.name.0.last() {
+                    Some(ident) => ident.value.to_lowercase(),
+                    None => return,
+                };
+                if !["avg", "max", "min", "sum"].contains(&name.as_str()) {
+                    return;
+                }
+
+                for arg in func.args.iter() {
+                    let arg = match arg {
+                        FunctionArg::Named { arg, .. } => arg,
+                        FunctionArg::Unnamed(arg) => arg,
+                    };
+                    if let FunctionArgExpr::Expr(expr) = arg {
+                        self.check(ViolationKind::Aggregate, expr)
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Look through wrappers that don't change which column is being referenced.
+fn unwrap_expr(expr: &Expr) -> &Expr {
+    match expr {
+        Expr::Nested(expr)
+        | Expr::Cast { expr, .. }
+        | Expr::TryCast { expr, .. }
+        | Expr::SafeCast { expr, .. }
+        | Expr::Collate { expr, .. } => unwrap_expr(expr),
+        _ => expr,
+    }
+}
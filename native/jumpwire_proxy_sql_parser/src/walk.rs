@@ -0,0 +1,5 @@
+mod edits;
+mod visitor;
+
+pub use self::edits::Edits;
+pub use self::visitor::{holds_query, table_name, walk_statement, QueryVisitor};
@@ -0,0 +1,37 @@
+use sqlparser::ast::{Expr, Statement, VisitMut, VisitorMut};
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+
+/// Replacements for expressions of a statement, collected while walking it
+/// read-only and applied afterwards in a single mutable pass.
+///
+/// Expressions are keyed by their address, which is stable for as long as
+/// the statement isn't changed. Edits must therefore be applied to the same
+/// statement they were collected from, before anything else changes it.
+#[derive(Default)]
+pub struct Edits(HashMap<*const Expr, Expr>);
+
+impl Edits {
+    pub fn replace(&mut self, expr: &Expr, replacement: Expr) {
+        self.0.insert(expr as *const Expr, replacement);
+    }
+
+    pub fn apply(mut self, statement: &mut Statement) {
+        if !self.0.is_empty() {
+            let _ = statement.visit(&mut self);
+        }
+    }
+}
+
+impl VisitorMut for Edits {
+    type Break = ();
+
+    // replacing after the children are visited means a replacement is never
+    // descended into, so its own nodes can't be mistaken for edited ones
+    fn post_visit_expr(&mut self, expr: &mut Expr) -> ControlFlow<Self::Break> {
+        if let Some(replacement) = self.0.remove(&(expr as *const Expr)) {
+            *expr = replacement
+        }
+        ControlFlow::Continue(())
+    }
+}
@@ -0,0 +1,209 @@
+use sqlparser::ast::{
+    CopySource, Expr, Ident, ObjectName, Query, Select, SetExpr, Statement, Table, TableFactor,
+    Visit, Visitor,
+};
+use std::ops::ControlFlow;
+
+/// Hooks called when entering and leaving each node of a statement, in the
+/// order the nodes appear.
+///
+/// sqlparser's own `Visitor` only reaches statements, relations, table
+/// factors and expressions. Queries and SELECTs are walked here instead, so
+/// that a query is entered before any of its CTEs, SELECTs or expressions
+/// and exited after all of them.
+pub trait QueryVisitor {
+    fn enter_statement(&mut self, _statement: &Statement) {}
+    fn exit_statement(&mut self, _statement: &Statement) {}
+    fn enter_query(&mut self, _query: &Query) {}
+    fn exit_query(&mut self, _query: &Query) {}
+    fn enter_select(&mut self, _select: &Select) {}
+    fn exit_select(&mut self, _select: &Select) {}
+    fn enter_table_factor(&mut self, _factor: &TableFactor) {}
+    fn exit_table_factor(&mut self, _factor: &TableFactor) {}
+    fn enter_relation(&mut self, _relation: &ObjectName) {}
+    fn enter_expr(&mut self, _expr: &Expr) {}
+    fn exit_expr(&mut self, _expr: &Expr) {}
+}
+
+/// Call the hooks of a visitor for every node of a statement.
+pub fn walk_statement<V: QueryVisitor>(statement: &Statement, visitor: &mut V) {
+    let mut walker = Walker {
+        visitor,
+        skipped: 0,
+    };
+    walker.walk_statement(statement)
+}
+
+/// Whether an expression contains a query of its own, such as `EXISTS (...)`.
+pub fn holds_query(expr: &Expr) -> bool {
+    matches!(
+        expr,
+        Expr::InSubquery { .. } | Expr::Exists { .. } | Expr::Subquery(_) | Expr::ArraySubquery(_)
+    )
+}
+
+/// The name of the table read by `TABLE name`, a shorthand for
+/// `SELECT * FROM name`. sqlparser keeps it as plain strings instead of an
+/// `ObjectName`, so its own visitor never reports it as a relation.
+pub fn table_name(table: &Table) -> Option<ObjectName> {
+    let name = table.table_name.as_ref()?;
+    let idents = table
+        .schema_name
+        .iter()
+        .chain(Some(name))
+        .map(|part| Ident::new(part.as_str()))
+        .collect();
+    Some(ObjectName(idents))
+}
+
+/// Walks statements and queries by hand, handing everything else to
+/// sqlparser's visitor. A subquery is walked as soon as the visitor reaches
+/// the expression or table factor holding it, after which the visitor's own
+/// descent into that subquery is skipped.
+struct Walker<'a, V> {
+    visitor: &'a mut V,
+    skipped: usize,
+}
+
+impl<'a, V: QueryVisitor> Walker<'a, V> {
+    fn walk_statement(&mut self, statement: &Statement) {
+        self.visitor.enter_statement(statement);
+        match statement {
+            Statement::Query(query) => self.walk_query(query),
+            Statement::Insert {
+                table_name,
+                source,
+                on,
+                returning,
+                ..
+            } => {
+                self.visitor.enter_relation(table_name);
+                self.walk_query(source);
+                let _ = on.visit(self);
+                let _ = returning.visit(self);
+            }
+            Statement::CreateTable {
+                name,
+                query: Some(query),
+                ..
+            } => {
+                self.visitor.enter_relation(name);
+                self.walk_query(query)
+            }
+            Statement::CreateView { query, .. } => self.walk_query(query),
+            Statement::Copy {
+                source: CopySource::Query(query),
+                ..
+            } => self.walk_query(query),
+            Statement::Explain { statement, .. } => self.walk_statement(statement),
+            _ => {
+                let _ = statement.visit(self);
+            }
+        }
+        self.visitor.exit_statement(statement)
+    }
+
+    fn walk_query(&mut self, query: &Query) {
+        self.visitor.enter_query(query);
+        if let Some(with) = &query.with {
+            for cte in with.cte_tables.iter() {
+                self.walk_query(&cte.query)
+            }
+        }
+        self.walk_body(&query.body);
+        let _ = query.order_by.visit(self);
+        let _ = query.limit.visit(self);
+        let _ = query.offset.visit(self);
+        let _ = query.fetch.visit(self);
+        let _ = query.locks.visit(self);
+        self.visitor.exit_query(query)
+    }
+
+    fn walk_body(&mut self, body: &SetExpr) {
+        match body {
+            SetExpr::Select(select) => {
+                self.visitor.enter_select(select);
+                let _ = select.visit(self);
+                self.visitor.exit_select(select)
+            }
+            SetExpr::Query(query) => self.walk_query(query),
+            SetExpr::SetOperation { left, right, .. } => {
+                self.walk_body(left);
+                self.walk_body(right)
+            }
+            SetExpr::Insert(statement) => self.walk_statement(statement),
+            SetExpr::Table(table) => {
+                if let Some(name) = table_name(table) {
+                    self.visitor.enter_relation(&name)
+                }
+            }
+            _ => {
+                let _ = body.visit(self);
+            }
+        }
+    }
+}
+
+impl<'a, V: QueryVisitor> Visitor for Walker<'a, V> {
+    type Break = ();
+
+    fn pre_visit_relation(&mut self, relation: &ObjectName) -> ControlFlow<Self::Break> {
+        if self.skipped == 0 {
+            self.visitor.enter_relation(relation)
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_table_factor(&mut self, factor: &TableFactor) -> ControlFlow<Self::Break> {
+        if self.skipped == 0 {
+            self.visitor.enter_table_factor(factor);
+            if let TableFactor::Derived { subquery, .. } = factor {
+                self.walk_query(subquery)
+            }
+        }
+        if let TableFactor::Derived { .. } = factor {
+            self.skipped += 1
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn post_visit_table_factor(&mut self, factor: &TableFactor) -> ControlFlow<Self::Break> {
+        if let TableFactor::Derived { .. } = factor {
+            self.skipped -= 1
+        }
+        if self.skipped == 0 {
+            self.visitor.exit_table_factor(factor)
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_expr(&mut self, expr: &Expr) -> ControlFlow<Self::Break> {
+        if self.skipped == 0 {
+            self.visitor.enter_expr(expr);
+            match expr {
+                Expr::InSubquery { expr, subquery, .. } => {
+                    let _ = expr.visit(self);
+                    self.walk_query(subquery)
+                }
+                Expr::Exists { subquery, .. }
+                | Expr::Subquery(subquery)
+                | Expr::ArraySubquery(subquery) => self.walk_query(subquery),
+                _ => (),
+            }
+        }
+        if holds_query(expr) {
+            self.skipped += 1
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn post_visit_expr(&mut self, expr: &Expr) -> ControlFlow<Self::Break> {
+        if holds_query(expr) {
+            self.skipped -= 1
+        }
+        if self.skipped == 0 {
+            self.visitor.exit_expr(expr)
+        }
+        ControlFlow::Continue(())
+    }
+}
@@ -1,6 +1,7 @@
 defmodule JumpWire.Proxy.PostgresTest do
   use JumpWire.ProxyCase, async: false
   alias JumpWire.Proxy.Postgres.Setup
+  alias JumpWire.Proxy.SQL.Parser
 
   @moduletag db: "postgres"
 
@@ -837,6 +838,131 @@ defmodule JumpWire.Proxy.PostgresTest do
       )
   end
 
+  test "checking policies when binding prepared statements", %{
+    conn: conn, params: params, org_id: org_id, manifest: manifest, schema: schema, table: table
+  } do
+    assert :ok == Setup.enable_database(manifest)
+    assert :ok == Setup.enable_table(manifest, schema)
+    insert_fake_rows(conn, table)
+
+    {:ok, pid} = Postgrex.start_link(params)
+    assert {:ok, query} = Postgrex.prepare(pid, "select_secret", "SELECT value FROM #{table}")
+    assert {:ok, _} = Postgrex.execute(pid, query, [])
+
+    # block reads after the statement has been prepared
+    policy = %JumpWire.Policy{
+      version: 2,
+      id: Uniq.UUID.uuid4(),
+      handling: :block,
+      label: "secret",
+      organization_id: org_id,
+      apply_on_match: true,
+      attributes: [MapSet.new(["select:secret"])],
+    }
+    key = {org_id, policy.id}
+
+    on_exit fn -> JumpWire.GlobalConfig.delete(:policies, key) end
+    JumpWire.GlobalConfig.put(:policies, key, policy)
+
+    assert {:error, %Postgrex.Error{postgres: %{code: :insufficient_privilege}}} =
+      Postgrex.execute(pid, query, [])
+  end
+
+  test "binding prepared statements that can't be parsed", %{
+    conn: conn, params: params, org_id: org_id, manifest: manifest, schema: schema, table: table
+  } do
+    assert :ok == Setup.enable_database(manifest)
+    assert :ok == Setup.enable_table(manifest, schema)
+    insert_fake_rows(conn, table)
+
+    # TABLESAMPLE isn't supported by the parser, so the statement is only scanned
+    # when it's parsed and is never registered
+    name = "unparsed_#{System.unique_integer([:positive])}"
+    query = "SELECT id FROM #{table} TABLESAMPLE SYSTEM (100)"
+    {:ok, pid} = Postgrex.start_link(params)
+    assert {:ok, prepared} = Postgrex.prepare(pid, name, query)
+    assert {:ok, %{num_rows: 4}} = Postgrex.execute(pid, prepared, [])
+
+    policy = %JumpWire.Policy{
+      version: 2,
+      id: Uniq.UUID.uuid4(),
+      handling: :block,
+      label: "secret",
+      organization_id: org_id,
+      apply_on_match: true,
+      attributes: [MapSet.new(["select:secret"])],
+    }
+    key = {org_id, policy.id}
+
+    on_exit fn -> JumpWire.GlobalConfig.delete(:policies, key) end
+    JumpWire.GlobalConfig.put(:policies, key, policy)
+
+    assert {:error, %Postgrex.Error{postgres: %{code: :insufficient_privilege}}} =
+      Postgrex.prepare(pid, "#{name}_blocked", query)
+  end
+
+  test "applying policies to statements that can't be parsed", %{
+    conn: conn, params: params, org_id: org_id, manifest: manifest, schema: schema, table: table
+  } do
+    assert :ok == Setup.enable_database(manifest)
+    assert :ok == Setup.enable_table(manifest, schema)
+    insert_fake_rows(conn, table)
+
+    # FOR KEY SHARE isn't supported by the parser, so the query is only scanned
+    query = "SELECT value FROM #{table} FOR KEY SHARE"
+    {:ok, pid} = Postgrex.start_link(params)
+    assert {:ok, _} = Postgrex.query(pid, query, [])
+
+    policy = %JumpWire.Policy{
+      version: 2,
+      id: Uniq.UUID.uuid4(),
+      handling: :block,
+      label: "secret",
+      organization_id: org_id,
+      apply_on_match: true,
+      attributes: [MapSet.new(["select:secret"])],
+    }
+    key = {org_id, policy.id}
+
+    on_exit fn -> JumpWire.GlobalConfig.delete(:policies, key) end
+    JumpWire.GlobalConfig.put(:policies, key, policy)
+
+    assert {:error, %Postgrex.Error{postgres: %{code: :insufficient_privilege}}} =
+      Postgrex.query(pid, query, [])
+  end
+
+  test "denying scanned statements with low confidence", %{params: params, manifest: manifest} do
+    assert :ok == Setup.enable_database(manifest)
+
+    # functions in a FROM clause could read any table
+    query = "SELECT * FROM generate_series(1, 3) WITH ORDINALITY"
+    {:ok, pid} = Postgrex.start_link(params)
+    assert {:ok, _} = Postgrex.query(pid, query, [])
+
+    proxy_opts = Application.get_env(:jumpwire, :proxy)
+    on_exit fn -> Application.put_env(:jumpwire, :proxy, proxy_opts) end
+    Application.put_env(:jumpwire, :proxy, Keyword.put(proxy_opts, :deny_low_confidence_scans, true))
+
+    {:ok, pid} = Postgrex.start_link(params)
+    assert {:error, %Postgrex.Error{postgres: %{code: :insufficient_privilege}}} =
+      Postgrex.query(pid, query, [])
+  end
+
+  test "closing prepared statements", %{params: params, manifest: manifest, schema: schema, table: table} do
+    assert :ok == Setup.enable_database(manifest)
+    assert :ok == Setup.enable_table(manifest, schema)
+
+    name = "closed_#{System.unique_integer([:positive])}"
+    {:ok, pid} = Postgrex.start_link(params)
+    assert {:ok, query} = Postgrex.prepare(pid, name, "SELECT value FROM #{table}")
+
+    registry = prepared_statement_registry(name)
+    assert {:ok, _, _, _} = Parser.fetch_prepared(registry, name)
+
+    assert :ok = Postgrex.close(pid, query)
+    assert {:error, _} = Parser.fetch_prepared(registry, name)
+  end
+
   test "applying request filter policy", %{
     conn: conn, params: params, org_id: org_id, manifest: manifest, schema: schema, table: table
   } do
@@ -868,6 +994,14 @@ defmodule JumpWire.Proxy.PostgresTest do
     assert {:ok, %{rows: [[^id]]}} = Postgrex.query(pid, "SELECT id FROM #{table}", [])
   end
 
+  # Find the registry of the proxy connection that prepared the named statement.
+  defp prepared_statement_registry(name) do
+    JumpWire.Proxy.Postgres
+    |> :ranch.procs(:connections)
+    |> Stream.map(fn pid -> :sys.get_state(pid).prepared_statements end)
+    |> Enum.find(fn registry -> match?({:ok, _, _, _}, Parser.fetch_prepared(registry, name)) end)
+  end
+
   defp insert_fake_rows(conn, table) do
     rows = [
       [Faker.Gov.Us.ssn, Faker.Phone.EnUs.phone],
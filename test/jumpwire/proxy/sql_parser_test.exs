@@ -1053,6 +1053,904 @@ defmodule JumpWire.Proxy.SQL.ParserTest do
     assert normalize(sql) == normalize(expected)
   end
 
+  test "finding column values in an insert" do
+    query = "INSERT INTO users (name, ssn) VALUES ('alice', '123'), ('bob', NULL), ('carol', '789'::text) ON CONFLICT (name) DO UPDATE SET ssn = '456'"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert {_, [{0, "ssn", "123"}, {1, "ssn", "789"}, {2, "ssn", "456"}]} = Parser.find_column_values(ref, "users", ["SSN"])
+    assert {_, []} = Parser.find_column_values(ref, "accounts", ["ssn"])
+  end
+
+  test "replacing column values in an insert" do
+    query = "INSERT INTO users (name, ssn) VALUES ('alice', '123'), ('bob', '456')"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert {version, [{0, "ssn", "123"}, {1, "ssn", "456"}]} = Parser.find_column_values(ref, "users", ["ssn"])
+    assert :ok = Parser.replace_column_values(ref, version, "users", ["ssn"], [{0, "enc:abc"}, {1, "enc:def"}])
+    expected = "INSERT INTO users (name, ssn) VALUES ('alice', 'enc:abc'), ('bob', 'enc:def')"
+    assert {:ok, sql} = Parser.to_sql(ref)
+    assert normalize(sql) == normalize(expected)
+  end
+
+  test "replacing column values in an update" do
+    query = "UPDATE users SET ssn = '123', name = 'alice' WHERE id = 1"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert {version, [{0, "ssn", "123"}]} = Parser.find_column_values(ref, "users", ["ssn"])
+    assert :invalid_index = Parser.replace_column_values(ref, version, "users", ["ssn"], [{1, "enc:abc"}])
+    assert :ok = Parser.replace_column_values(ref, version, "users", ["ssn"], [{0, "enc:abc"}])
+    expected = "UPDATE users SET ssn = 'enc:abc', name = 'alice' WHERE id = 1"
+    assert {:ok, sql} = Parser.to_sql(ref)
+    assert normalize(sql) == normalize(expected)
+  end
+
+  test "replacing column values after the statement changed" do
+    query = "UPDATE users SET ssn = '123' WHERE id = 1"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert {version, [{0, "ssn", "123"}]} = Parser.find_column_values(ref, "users", ["ssn"])
+    assert :ok = Parser.add_table_selection(ref, "users", "org_id", :eq, 2)
+    assert :stale = Parser.replace_column_values(ref, version, "users", ["ssn"], [{0, "enc:abc"}])
+  end
+
+  test "finding predicate values" do
+    query = """
+    SELECT * FROM users u JOIN accounts a ON a.user_id = u.id
+    WHERE u.ssn = '123' AND (a.ssn = '999' OR ssn IN ('456', '789'))
+    """
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert {_, [{0, "ssn", "123"}, {1, "ssn", "456"}, {2, "ssn", "789"}]} =
+      Parser.find_predicate_values(ref, "users", ["ssn"])
+  end
+
+  test "finding predicate values in mixed lists, joins and HAVING" do
+    query = "SELECT * FROM users WHERE ssn IN ('123', $1, '456'::text)"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert {version, [{0, "ssn", "123"}, {1, "ssn", "456"}]} = Parser.find_predicate_values(ref, "users", ["ssn"])
+    assert :ok = Parser.replace_predicate_values(ref, version, "users", ["ssn"], [{0, "tok:a"}, {1, "tok:b"}])
+    expected = "SELECT * FROM users WHERE ssn IN ('tok:a', $1, CAST('tok:b' AS TEXT))"
+    assert {:ok, sql} = Parser.to_sql(ref)
+    assert normalize(sql) == normalize(expected)
+
+    query = """
+    SELECT u.ssn, count(*) FROM orders o JOIN users u ON u.id = o.user_id AND u.ssn = '123'
+    WHERE o.total > 10 GROUP BY u.ssn HAVING u.ssn <> '456'
+    """
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert {_, [{0, "ssn", "123"}, {1, "ssn", "456"}]} = Parser.find_predicate_values(ref, "users", ["ssn"])
+
+    key = Parser.blind_index_key("secret")
+    query = "SELECT * FROM users WHERE ssn IN ('123', $1)"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert {:error, {:unsupported_value, _}} = Parser.add_blind_index(ref, key, "users", [{"ssn", "ssn_bidx"}])
+  end
+
+  test "replacing predicate values in a subquery" do
+    query = "DELETE FROM orders WHERE user_id IN (SELECT id FROM users WHERE '123' = ssn)"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert {version, [{0, "ssn", "123"}]} = Parser.find_predicate_values(ref, "users", ["ssn"])
+    assert :ok = Parser.replace_predicate_values(ref, version, "users", ["ssn"], [{0, "tok:abc"}])
+    expected = "DELETE FROM orders WHERE user_id IN (SELECT id FROM users WHERE 'tok:abc' = ssn)"
+    assert {:ok, sql} = Parser.to_sql(ref)
+    assert normalize(sql) == normalize(expected)
+  end
+
+  test "replacing predicate values outside of a WHERE clause" do
+    query = "UPDATE accounts SET flagged = (SELECT count(*) > 0 FROM users WHERE CASE WHEN ssn = '123' THEN true END)"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert {version, [{0, "ssn", "123"}]} = Parser.find_predicate_values(ref, "users", ["ssn"])
+    assert :ok = Parser.replace_predicate_values(ref, version, "users", ["ssn"], [{0, "tok:abc"}])
+    expected = "UPDATE accounts SET flagged = (SELECT count(*) > 0 FROM users WHERE CASE WHEN ssn = 'tok:abc' THEN true END)"
+    assert {:ok, sql} = Parser.to_sql(ref)
+    assert normalize(sql) == normalize(expected)
+  end
+
+  test "adding a blind index to a query" do
+    key = Parser.blind_index_key("secret")
+    hmac = :crypto.mac(:hmac, :sha256, "secret", "a@b.c") |> Base.encode16(case: :lower)
+
+    query = "SELECT * FROM users WHERE email = 'a@b.c' AND name = 'alice'"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert :ok = Parser.add_blind_index(ref, key, "users", [{"email", "email_bidx"}])
+    expected = "SELECT * FROM users WHERE email_bidx = '#{hmac}' AND name = 'alice'"
+    assert {:ok, sql} = Parser.to_sql(ref)
+    assert normalize(sql) == normalize(expected)
+
+    # predicates are indexed wherever they appear, with signs and casts kept
+    hmac5 = :crypto.mac(:hmac, :sha256, "secret", "-5") |> Base.encode16(case: :lower)
+    query = """
+    WITH recent AS (SELECT id FROM users WHERE email = 'a@b.c'::text)
+    SELECT CASE WHEN u.pin = -5 THEN 1 END, coalesce(u.email = 'a@b.c', false)
+    FROM users u JOIN recent r ON r.id = u.id
+    """
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert :ok = Parser.add_blind_index(ref, key, "users", [{"email", "email_bidx"}, {"pin", "pin_bidx"}])
+    expected = """
+    WITH recent AS (SELECT id FROM users WHERE email_bidx = '#{hmac}'::text)
+    SELECT CASE WHEN u.pin_bidx = '#{hmac5}' THEN 1 END, coalesce(u.email_bidx = '#{hmac}', false)
+    FROM users u JOIN recent r ON r.id = u.id
+    """
+    assert {:ok, sql} = Parser.to_sql(ref)
+    assert normalize(sql) == normalize(expected)
+
+    # a comparison to another column isn't a search for a value
+    query = "SELECT * FROM users u JOIN accounts a ON a.email = u.email"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert :ok = Parser.add_blind_index(ref, key, "users", [{"email", "email_bidx"}])
+    assert {:ok, sql} = Parser.to_sql(ref)
+    assert normalize(sql) == normalize(query)
+  end
+
+  test "adding a blind index to inserts and updates" do
+    key = Parser.blind_index_key("secret")
+    hmac = :crypto.mac(:hmac, :sha256, "secret", "a@b.c") |> Base.encode16(case: :lower)
+
+    query = "INSERT INTO users (name, email) VALUES ('alice', 'a@b.c')"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert :ok = Parser.add_blind_index(ref, key, "users", [{"email", "email_bidx"}])
+    expected = "INSERT INTO users (name, email, email_bidx) VALUES ('alice', 'a@b.c', '#{hmac}')"
+    assert {:ok, sql} = Parser.to_sql(ref)
+    assert normalize(sql) == normalize(expected)
+
+    query = "UPDATE users SET email = 'a@b.c' WHERE id = 1"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert :ok = Parser.add_blind_index(ref, key, "users", [{"email", "email_bidx"}])
+    expected = "UPDATE users SET email = 'a@b.c', email_bidx = '#{hmac}' WHERE id = 1"
+    assert {:ok, sql} = Parser.to_sql(ref)
+    assert normalize(sql) == normalize(expected)
+
+    query = "INSERT INTO users (email) SELECT email FROM old_users"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert {:error, {:unsupported_insert_source, _}} =
+      Parser.add_blind_index(ref, key, "users", [{"email", "email_bidx"}])
+
+    query = "INSERT INTO users VALUES ('alice', 'a@b.c')"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert {:error, {:unsupported_statement, _}} =
+      Parser.add_blind_index(ref, key, "users", [{"email", "email_bidx"}])
+
+    # parameterized inserts set the index column themselves
+    query = "INSERT INTO users (email, email_bidx) VALUES ($1, $2)"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert :ok = Parser.add_blind_index(ref, key, "users", [{"email", "email_bidx"}])
+    assert {:ok, sql} = Parser.to_sql(ref)
+    assert normalize(sql) == normalize(query)
+
+    query = "INSERT INTO users (email) VALUES ($1)"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert {:error, {:unsupported_value, _}} =
+      Parser.add_blind_index(ref, key, "users", [{"email", "email_bidx"}])
+
+    # inserts nested in a CTE are indexed too
+    query = """
+    WITH added AS (INSERT INTO users (email) VALUES ('a@b.c'::text) RETURNING id)
+    SELECT id FROM added
+    """
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert :ok = Parser.add_blind_index(ref, key, "users", [{"email", "email_bidx"}])
+    expected = """
+    WITH added AS (INSERT INTO users (email, email_bidx) VALUES ('a@b.c'::text, '#{hmac}') RETURNING id)
+    SELECT id FROM added
+    """
+    assert {:ok, sql} = Parser.to_sql(ref)
+    assert normalize(sql) == normalize(expected)
+  end
+
+  test "validating protected columns" do
+    query = """
+    SELECT u.name, sum(u.salary) FROM users u JOIN accounts a ON a.ssn = u.ssn
+    WHERE u.ssn LIKE '123%' AND salary > 100
+    GROUP BY u.name ORDER BY u.ssn
+    """
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    violations = Parser.validate_columns(ref, "users", ["ssn", "salary"])
+    assert MapSet.new(violations, fn {kind, column, _} -> {kind, column} end) == MapSet.new([
+      {:order_by, "ssn"},
+      {:join, "ssn"},
+      {:aggregate, "salary"},
+      {:like, "ssn"},
+      {:comparison, "salary"},
+    ])
+    assert {:order_by, "ssn", ~s(ORDER BY cannot be used on protected column "ssn")} in violations
+  end
+
+  test "validating protected columns in other tables" do
+    query = "UPDATE accounts SET balance = balance + 1 WHERE id IN (SELECT id FROM users WHERE ssn = '123')"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert [] = Parser.validate_columns(ref, "users", ["ssn", "balance"])
+    assert [{:arithmetic, "balance", _}] = Parser.validate_columns(ref, "accounts", ["ssn", "balance"])
+  end
+
+  test "validating protected columns in window and aggregate ordering" do
+    query = "SELECT rank() OVER (PARTITION BY ssn ORDER BY salary) FROM users"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert [{:order_by, "salary", _}] = Parser.validate_columns(ref, "users", ["ssn", "salary"])
+
+    query = "SELECT rank() OVER w FROM users WINDOW w AS (ORDER BY ssn)"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert [{:order_by, "ssn", _}] = Parser.validate_columns(ref, "users", ["ssn"])
+
+    query = "SELECT string_agg(name, ',' ORDER BY ssn) FROM users"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert [{:order_by, "ssn", _}] = Parser.validate_columns(ref, "users", ["ssn"])
+  end
+
+  test "validating protected columns in patterns" do
+    query = "SELECT * FROM users WHERE 'abc' LIKE ssn"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert [{:like, "ssn", _}] = Parser.validate_columns(ref, "users", ["ssn"])
+
+    query = "SELECT * FROM users WHERE name ILIKE ssn OR name SIMILAR TO salary"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert [{:like, "ssn", _}, {:like, "salary", _}] =
+      Parser.validate_columns(ref, "users", ["ssn", "salary"])
+  end
+
+  test "validating protected columns in regular expressions" do
+    for op <- ["~", "~*", "!~", "!~*"] do
+      query = "SELECT * FROM users WHERE ssn #{op} '^123'"
+      assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+      assert [{:like, "ssn", _}] = Parser.validate_columns(ref, "users", ["ssn"])
+    end
+  end
+
+  test "mapping placeholders to columns" do
+    query = "SELECT * FROM public.users u JOIN accounts a ON a.user_id = u.id WHERE u.ssn = $2 AND a.id IN ($1, $3) AND length($4) > 1"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert [
+      %{index: 2, usage: :compare, schema: "public", table: "users", column: "ssn"},
+      %{index: 1, usage: :compare, schema: nil, table: "accounts", column: "id"},
+      %{index: 3, usage: :compare, schema: nil, table: "accounts", column: "id"},
+      %{index: 4, usage: :other, table: nil, column: nil},
+    ] = Parser.parameter_targets(ref)
+  end
+
+  test "mapping placeholders in inserts and updates" do
+    query = "INSERT INTO users (name, ssn) VALUES (?, ?) ON CONFLICT (name) DO UPDATE SET ssn = ?"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert [
+      %{index: 1, usage: :insert, table: "users", column: "name"},
+      %{index: 2, usage: :insert, table: "users", column: "ssn"},
+      %{index: 3, usage: :assign, table: "users", column: "ssn"},
+    ] = Parser.parameter_targets(ref)
+
+    query = "UPDATE users SET ssn = $1::text WHERE id = $2"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert [
+      %{index: 1, usage: :assign, table: "users", column: "ssn"},
+      %{index: 2, usage: :compare, table: "users", column: "id"},
+    ] = Parser.parameter_targets(ref)
+  end
+
+  test "storing prepared statements" do
+    registry = Parser.prepared_registry()
+    query = "SELECT * FROM users WHERE ssn = $1"
+    assert {:ok, [{statement, ref}]} = Parser.prepare_statement(registry, "find_user", query, [25])
+    assert :ok = Parser.add_table_selection(ref, "users", "org", :eq, "abc")
+
+    assert {:ok, [{^statement, ref}], [25], [%{index: 1, column: "ssn"}]} =
+      Parser.fetch_prepared(registry, "find_user")
+    assert {:ok, sql} = Parser.to_sql(ref)
+    assert normalize(sql) == normalize(query)
+
+    assert :ok = Parser.close_prepared(registry, "find_user")
+    assert {:error, {:not_found, _}} = Parser.fetch_prepared(registry, "find_user")
+  end
+
+  test "rewriting a statement concurrently" do
+    query = "SELECT * FROM users"
+    {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+
+    1..20
+    |> Task.async_stream(fn i ->
+      Parser.add_table_selection(ref, "users", "org#{i}", :eq, i)
+    end)
+    |> Enum.each(fn result -> assert {:ok, :ok} = result end)
+
+    assert {:ok, sql} = Parser.to_sql(ref)
+    for i <- 1..20, do: assert sql =~ "org#{i} = #{i}"
+  end
+
+  test "committing and rolling back rewrites" do
+    query = "SELECT * FROM users"
+    {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+
+    txn = Parser.begin_rewrite(ref)
+    assert :ok = Parser.add_table_selection(txn, "users", "org", :eq, 1)
+    assert {:ok, sql} = Parser.to_sql(ref)
+    assert normalize(sql) == normalize(query)
+
+    assert :ok = Parser.rollback_rewrite(txn)
+    assert :ok = Parser.add_table_selection(txn, "users", "org", :eq, 2)
+    assert :ok = Parser.commit_rewrite(ref, txn)
+    assert {:ok, sql} = Parser.to_sql(ref)
+    assert normalize(sql) == "select * from users where org=2"
+
+    txn = Parser.begin_rewrite(ref)
+    assert :ok = Parser.add_table_selection(ref, "users", "team", :eq, 3)
+    assert :conflict = Parser.commit_rewrite(ref, txn)
+    assert :not_a_transaction = Parser.rollback_rewrite(ref)
+  end
+
+  test "replacing values found in a transaction" do
+    query = "SELECT * FROM users WHERE ssn = '123'"
+    {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+
+    txn = Parser.begin_rewrite(ref)
+    assert {version, [{0, "ssn", _}]} = Parser.find_predicate_values(txn, "users", ["ssn"])
+    assert :stale = Parser.replace_predicate_values(ref, version, "users", ["ssn"], [{0, "abc"}])
+    assert :ok = Parser.replace_predicate_values(txn, version, "users", ["ssn"], [{0, "abc"}])
+    assert :ok = Parser.commit_rewrite(ref, txn)
+    assert {:ok, sql} = Parser.to_sql(ref)
+    assert normalize(sql) == "select * from users where ssn='abc'"
+  test "parsing with limits" do
+    query = "SELECT * FROM users WHERE id = ((((1))))"
+    # the statement, the query, the WHERE expression and four nested expressions
+    assert {:ok, _} = Parser.parse_postgresql(query, max_bytes: 100, max_depth: 7, max_tokens: 20)
+    assert {:error, {:query_too_large, _}} = Parser.parse_postgresql(query, max_bytes: 10)
+    assert {:error, {:too_many_tokens, _}} = Parser.parse_postgresql(query, max_tokens: 5)
+    assert {:error, {:recursion_limit_exceeded, _}} = Parser.parse_postgresql(query, max_depth: 6)
+  end
+
+  test "preparing statements with limits" do
+    registry = Parser.prepared_registry()
+    query = "SELECT * FROM users WHERE id = ((($1)))"
+    assert {:error, {:recursion_limit_exceeded, _}} =
+      Parser.prepare_statement(registry, "nested", query, [], max_depth: 4)
+    assert {:error, {:not_found, _}} = Parser.fetch_prepared(registry, "nested")
+
+    assert {:ok, _} = Parser.prepare_statement(registry, "nested", query, [23], max_tokens: 20)
+    assert {:ok, [_], [23], _} = Parser.fetch_prepared(registry, "nested")
+
+    # the text is kept, so rewrites are spliced in around the comment
+    query = "select *\n  /* app='web' */ from users where id = $1"
+    assert {:ok, _} = Parser.prepare_statement(registry, "find_user", query, [23], max_tokens: 20)
+    assert {:ok, [{_, ref}], _, _} = Parser.fetch_prepared(registry, "find_user")
+    assert :ok = Parser.add_table_selection(ref, "users", "org", :eq, "abc")
+    assert {:ok, sql} = Parser.splice_sql(ref)
+    assert sql =~ "/* app='web' */ from users"
+  end
+
+  test "parsing large queries" do
+    values = Enum.map_join(1..5000, ", ", fn i -> "(#{i}, 'user #{i}')" end)
+    query = "INSERT INTO users (id, name) VALUES #{values}"
+    assert byte_size(query) > 64 * 1024
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert {:ok, _} = Parser.to_sql(ref)
+  end
+
+  test "parsing queries in other encodings" do
+    query = :unicode.characters_to_binary("SELECT * FROM users WHERE name = 'José'", :utf8, :latin1)
+    assert {:error, {:invalid_encoding, _}} = Parser.parse_postgresql(query)
+    assert {:error, {:unsupported_encoding, _}} = Parser.parse_postgresql(query, encoding: "EBCDIC")
+
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query, encoding: "LATIN1")
+    assert {version, [{0, "name", "José"}]} = Parser.find_predicate_values(ref, "users", ["name"])
+    assert :ok = Parser.replace_predicate_values(ref, version, "users", ["name"], [{0, "Zoë"}])
+    assert {:ok, sql} = Parser.to_sql(ref)
+    assert sql == :unicode.characters_to_binary("SELECT * FROM users WHERE name = 'Zoë'", :utf8, :latin1)
+
+    assert {version, _} = Parser.find_predicate_values(ref, "users", ["name"])
+    assert :ok = Parser.replace_predicate_values(ref, version, "users", ["name"], [{0, "名前"}])
+    assert {:error, {:invalid_encoding, _}} = Parser.to_sql(ref)
+  end
+
+  test "passing bytes through in SQL_ASCII queries" do
+    # mixes LATIN1 and UTF-8 bytes, which isn't valid in either encoding
+    query = "SELECT * FROM users WHERE name = 'Jos\xE9' OR name = 'Zoë'"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query, encoding: "SQL_ASCII")
+    assert {:ok, ^query} = Parser.to_sql(ref)
+  end
+
+  test "parse errors include their position" do
+    query = "SELECT *\nFROM users ORDER id"
+    assert {:error, {:parser_error, err}} = Parser.parse_postgresql(query)
+    assert %{line: 2, column: 18, offset: 26, position: 27, token: "id", sqlstate: "42601"} = err
+    assert err.message =~ "Expected BY"
+
+    assert {:error, {:too_many_tokens, err}} = Parser.parse_postgresql(query, max_tokens: 1)
+    assert %{sqlstate: "54000", line: nil, position: nil} = err
+  end
+
+  test "scanning tokens of a query" do
+    query = """
+    WITH recent AS (SELECT * FROM public.orders o WHERE o.created_at > now())
+    SELECT u.email, extract(year FROM u.created_at)
+    FROM users u, recent JOIN items i ON i.order_id = recent.id
+    WHERE u.name IS DISTINCT FROM 'x'
+    """
+    assert {:ok, result} = Parser.scan_postgresql(query)
+    assert result.confidence == :high
+    assert result.relations == [
+      %{schema: "public", name: "orders"},
+      %{schema: nil, name: "users"},
+      %{schema: nil, name: "items"},
+    ]
+    assert %{qualifier: "u", name: "email"} in result.columns
+    assert %{qualifier: "i", name: "order_id"} in result.columns
+
+    assert {:ok, result} = Parser.scan_postgresql("SELECT * FROM generate_series(1, 10)")
+    assert result.confidence == :low
+
+    query = "WITH totals(user_id, amount) AS (SELECT user_id, sum(total) FROM orders GROUP BY 1) SELECT * FROM totals"
+    assert {:ok, result} = Parser.scan_postgresql(query)
+    assert result.relations == [%{schema: nil, name: "orders"}]
+  end
+
+  test "scanning common table expressions" do
+    # a CTE shadows the table it reads from only after its own body
+    query = "WITH users AS (SELECT * FROM users) SELECT ssn FROM users"
+    assert {:ok, result} = Parser.scan_postgresql(query)
+    assert result.relations == [%{schema: nil, name: "users"}]
+
+    # names are only visible in the query their WITH clause belongs to
+    query = "SELECT * FROM (WITH x AS (SELECT 1) SELECT * FROM x) s, x"
+    assert {:ok, result} = Parser.scan_postgresql(query)
+    assert result.relations == [%{schema: nil, name: "x"}]
+
+    query = "WITH RECURSIVE t(n) AS (SELECT 1 UNION SELECT n FROM t) SELECT * FROM t, accounts"
+    assert {:ok, result} = Parser.scan_postgresql(query)
+    assert result.relations == [%{schema: nil, name: "accounts"}]
+
+    # `name AS (` outside of a WITH clause doesn't define a CTE
+    assert {:ok, result} = Parser.scan_postgresql("CREATE VIEW users AS (SELECT ssn FROM users)")
+    assert result.relations == [%{schema: nil, name: "users"}]
+
+    assert {:ok, result} = Parser.scan_postgresql("CREATE TABLE copy AS (SELECT * FROM users)")
+    assert result.relations == [
+      %{schema: nil, name: "copy"},
+      %{schema: nil, name: "users"},
+    ]
+  end
+
+  test "parsing PostgreSQL operator forms of LIKE and IS NULL" do
+    query = "SELECT * FROM users WHERE name ~~ 'a%' AND email !~~* '%@example.com' AND ssn ISNULL AND id NOTNULL"
+    expected = "select * from users where name like 'a%' and email not ilike '%@example.com' and ssn is null and id is not null"
+    assert {:ok, [_]} = parse_query(query, expected)
+
+    # separated tildes are a regex match against a bitwise NOT
+    query = "SELECT * FROM users WHERE flags ~ ~mask AND name ~~ 'a%'"
+    expected = "select * from users where flags ~ ~mask and name like 'a%'"
+    assert {:ok, [_]} = parse_query(query, expected)
+  end
+
+  test "parsing COPY options with PostgreSQL boolean spellings" do
+    query = "COPY users (id, name) TO STDOUT WITH (FORMAT csv, HEADER on, FREEZE 0, DELIMITER '|')"
+    expected = "copy users (id, name) to stdout (format csv, header, freeze false, delimiter '|')"
+    assert {:ok, [_]} = parse_query(query, expected)
+
+    query = "COPY users TO STDOUT WITH CSV HEADER FORCE QUOTE name"
+    expected = "copy users to stdout csv header force quote name"
+    assert {:ok, [_]} = parse_query(query, expected)
+
+    assert {:error, {:parser_error, _}} = Parser.parse_postgresql("COPY users TO STDOUT (HEADER MATCH)")
+  end
+
+  test "splitting statements" do
+    statements = [
+      "SELECT 'a;b', \"c;d\" FROM t",
+      "SELECT E'it\\'s;' /* a /* nested; */ comment */",
+      "CREATE FUNCTION f() RETURNS int AS $body$ SELECT 1; $body$ LANGUAGE sql",
+      "SELECT $$;$$ -- a comment;\n, $1",
+    ]
+    query = Enum.join(statements, ";\n  ") <> "; /* only a comment */ ;"
+
+    ranges = Parser.split_statements(query)
+    assert Enum.map(ranges, fn {start, len} -> binary_part(query, start, len) end) == statements
+
+    query = "SELECT 'a\\'; SELECT 'b'; SELECT 1"
+    assert [_, _, _] = Parser.split_statements(query)
+    assert [_] = Parser.split_statements(query, standard_conforming_strings: false)
+  end
+
+  test "rewriting bulk inserts" do
+    query = "INSERT INTO public.users (id, ssn, active) VALUES (1, '123', true), (2, 'it''s', NULL);"
+    assert {:ok, {ref, info}} = Parser.parse_bulk_insert(query)
+    assert info == %{schema: "public", table: "users", columns: ["id", "ssn", "active"], rows: 2}
+
+    assert [{0, _}, {1, _}] = ref |> Parser.bulk_insert_stream("ssn", 1) |> Enum.to_list()
+    assert :ok = Parser.replace_bulk_insert_values(ref, "ssn", [{1, "abc"}])
+    assert :invalid_index = Parser.replace_bulk_insert_values(ref, "ssn", [{2, "abc"}])
+    assert :unknown_column = Parser.bulk_insert_values(ref, "email", 0, 10)
+
+    assert {:ok, sql} = Parser.bulk_insert_to_sql(ref)
+    assert sql == "INSERT INTO public.users (id, ssn, active) VALUES (1, '123', true), (2, 'abc', NULL);"
+
+    for query <- [
+      "INSERT INTO users (id) VALUES (1) RETURNING id",
+      "INSERT INTO users (id) VALUES (1 + 1)",
+      "INSERT INTO users (id) SELECT 1",
+      "SELECT * FROM users",
+    ] do
+      assert {:error, {:not_bulk_insert, _}} = Parser.parse_bulk_insert(query)
+    end
+
+    # a backslash only escapes a quote when standard_conforming_strings is off
+    query = "INSERT INTO users (ssn, name) VALUES ('a\\', 'b')"
+    assert {:ok, {_ref, %{rows: 1}}} = Parser.parse_bulk_insert(query)
+    assert {:error, {:not_bulk_insert, _}} =
+      Parser.parse_bulk_insert(query, standard_conforming_strings: false)
+  end
+
+  test "caching parsed queries" do
+    query = "SELECT id, ssn FROM users WHERE id = 42 AND active"
+    %{hits: hits} = Parser.parse_cache_stats()
+
+    assert {:ok, [{first, first_ref}]} = Parser.parse_postgresql(query)
+    assert {:ok, [{second, second_ref}]} = Parser.parse_postgresql(query)
+    assert first == second
+    assert %{hits: new_hits, capacity: capacity} = Parser.parse_cache_stats()
+    assert new_hits > hits
+    assert capacity > 0
+
+    # rewriting one statement doesn't change another parsed from the same text
+    assert :ok = Parser.add_table_selection(first_ref, "users", "ssn", :eq, "123")
+    assert {:ok, rewritten} = Parser.to_sql(first_ref)
+    assert rewritten != query
+    assert {:ok, ^query} = Parser.to_sql(second_ref)
+
+    assert {:error, {:too_many_tokens, _}} = Parser.parse_postgresql(query, max_tokens: 5)
+
+    assert :ok = Parser.clear_parse_cache()
+    assert %{size: 0} = Parser.parse_cache_stats()
+  end
+
+  test "configuring the parse cache" do
+    on_exit fn -> Parser.configure_parse_cache(Application.get_env(:jumpwire, :parse_cache)) end
+
+    assert :ok = Parser.configure_parse_cache(capacity: 2, max_query_bytes: 32)
+    assert {:ok, _} = Parser.parse_postgresql("SELECT 1")
+    assert {:ok, _} = Parser.parse_postgresql("SELECT 2")
+    assert {:ok, _} = Parser.parse_postgresql("SELECT 1")
+    assert {:ok, _} = Parser.parse_postgresql("SELECT 3")
+    assert %{size: 2, capacity: 2} = Parser.parse_cache_stats()
+
+    # queries over the size limit are parsed but never cached
+    %{misses: misses} = Parser.parse_cache_stats()
+    query = "SELECT id, name, email FROM users WHERE id = 1"
+    assert {:ok, _} = Parser.parse_postgresql(query)
+    assert {:ok, _} = Parser.parse_postgresql(query)
+    assert %{misses: new_misses, size: 2} = Parser.parse_cache_stats()
+    assert new_misses >= misses + 2
+
+    # lowering the capacity evicts the least recently used queries
+    assert :ok = Parser.configure_parse_cache(capacity: 1, max_query_bytes: 32)
+    assert %{size: 1} = Parser.parse_cache_stats()
+  end
+
+  test "parsing with a projection" do
+    query = "WITH recent AS (SELECT * FROM public.orders) SELECT u.id, r.total FROM users u JOIN recent r ON r.user_id = u.id"
+    assert {:ok, [{projection, ref}]} = Parser.parse_postgresql(query, project: [:kind, :tables])
+    assert projection.kind == "Query"
+    assert projection.tables == [%{schema: "public", name: "orders"}, %{schema: nil, name: "users"}]
+    assert is_nil(projection.fields)
+
+    assert {:ok, [{%{fields: fields}, _}]} = Parser.parse_postgresql(query, project: [:fields])
+    assert %{qualifier: "u", name: "id"} in fields
+    assert %{qualifier: "r", name: "user_id"} in fields
+
+    assert {:ok, [{statement, _}]} = Parser.parse_postgresql(query)
+    assert {:ok, ^statement} = Parser.statement_term(ref)
+
+    query = "INSERT INTO users (id, ssn) VALUES (1, '123')"
+    assert {:ok, [{projection, _}]} = Parser.parse_postgresql(query, project: [:kind, :fields])
+    assert projection.kind == "Insert"
+    assert projection.fields == [%{qualifier: nil, name: "id"}, %{qualifier: nil, name: "ssn"}]
+
+    # a CTE only hides the table it shadows outside of its own body
+    query = "WITH users AS (SELECT * FROM users) SELECT ssn FROM users"
+    assert {:ok, [{projection, _}]} = Parser.parse_postgresql(query, project: [:tables])
+    assert projection.tables == [%{schema: nil, name: "users"}]
+  end
+
+  test "replacing a statement from Elixir" do
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql("SELECT id FROM users")
+    assert {:ok, [{_, other}]} = Parser.parse_postgresql("DELETE FROM orders WHERE id = 1")
+
+    assert {:ok, ast} = Parser.statement_ast(other)
+    assert :ok = Parser.replace_statement(ref, ast)
+    assert {:ok, "DELETE FROM orders WHERE id = 1"} = Parser.to_sql(ref)
+
+    assert {:error, {:invalid_statement, %{sqlstate: "XX000"}}} =
+      Parser.replace_statement(ref, %{not: "a statement"})
+    assert {:ok, "DELETE FROM orders WHERE id = 1"} = Parser.to_sql(ref)
+  end
+
+  test "splicing rewrites into the original query" do
+    query = "select *\n  /* app='web' */ from weather"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert {:ok, ^query} = Parser.splice_sql(ref)
+
+    assert :ok = Parser.add_table_selection(ref, "weather", "hello", :eq, "you")
+    assert {:ok, "select *\n  /* app='web' */ from weather WHERE hello = 'you'"} = Parser.splice_sql(ref)
+
+    query = "insert into users (name, ssn)\n  values ('alice', E'123'), ('bob', '456'); SELECT 1"
+    assert {:ok, [{_, ref}, _]} = Parser.parse_postgresql(query)
+    assert {version, _} = Parser.find_column_values(ref, "users", ["ssn"])
+    assert :ok = Parser.replace_column_values(ref, version, "users", ["ssn"], [{1, "enc:def"}])
+    assert {:ok, "insert into users (name, ssn)\n  values ('alice', E'123'), ('bob', 'enc:def')"} =
+      Parser.splice_sql(ref)
+  end
+
+  test "reading and writing sqlcommenter tags" do
+    query = "SELECT * FROM users /* just a comment */ /*application='web',route='%2Fusers%2F\\'id',x=''*/"
+    assert {:ok, tags} = Parser.sql_comment_tags(query)
+    assert tags == %{"application" => "web", "route" => "/users/'id", "x" => ""}
+
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql("SELECT * FROM users")
+    assert :ok = Parser.add_sql_comment_tags(ref, trace_id: "abc", client: "a b")
+    assert :ok = Parser.add_sql_comment_tags(ref, %{"trace_id" => "def"})
+    assert {:ok, "SELECT * FROM users /*client='a%20b',trace_id='def'*/"} = Parser.to_sql(ref)
+
+    txn = Parser.begin_rewrite(ref)
+    assert :ok = Parser.add_sql_comment_tags(txn, trace_id: "ghi")
+    assert :ok = Parser.rollback_rewrite(txn)
+    assert {:ok, "SELECT * FROM users /*client='a%20b',trace_id='def'*/"} = Parser.to_sql(txn)
+
+    assert :ok = Parser.add_sql_comment_tags(txn, route: "/")
+    assert :ok = Parser.commit_rewrite(ref, txn)
+    assert {:ok, "SELECT * FROM users /*client='a%20b',route='%2F',trace_id='def'*/"} = Parser.to_sql(ref)
+
+    # tags are merged into a trailing sqlcommenter comment from the client
+    query = "select * from users /*application='web',trace_id='abc'*/"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert :ok = Parser.add_sql_comment_tags(ref, trace_id: "def")
+    assert {:ok, "select * from users /*application='web',trace_id='def'*/"} = Parser.splice_sql(ref)
+
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql("COPY users (id, name) FROM STDIN")
+    assert :ok = Parser.add_sql_comment_tags(ref, trace_id: "def")
+    assert {:ok, "COPY users (id, name) FROM STDIN"} = Parser.to_sql(ref)
+  end
+
+  test "applying several rewrites at once" do
+    query = "SELECT u.id, u.ssn, o.total FROM users u JOIN orders o ON o.user_id = u.id UNION SELECT id, ssn, 0 FROM users LIMIT 500"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+
+    ops = [
+      {:filter, "users", "org", :eq, "abc"},
+      {:mask, "users", "ssn", "***"},
+      {:limit, 100},
+      {:substitute, "orders", "orders_redacted"},
+      {:filter, "accounts", "org", :eq, "abc"},
+    ]
+    assert {:ok, [2, 2, 1, 1, 0]} = Parser.apply_rewrites(ref, ops)
+
+    expected = "SELECT u.id, '***' AS ssn, o.total FROM users AS u JOIN orders_redacted AS o ON o.user_id = u.id WHERE org = 'abc' UNION SELECT id, '***' AS ssn, 0 FROM users WHERE org = 'abc' LIMIT 100"
+    assert {:ok, sql} = Parser.to_sql(ref)
+    assert normalize(sql) == normalize(expected)
+
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql("SELECT * FROM users LIMIT $1")
+    assert {:ok, [1]} = Parser.apply_rewrites(ref, [{:limit, 10}])
+    assert {:ok, "SELECT * FROM users LIMIT CASE WHEN $1 < 10 THEN $1 ELSE 10 END"} = Parser.to_sql(ref)
+
+    # FETCH FIRST is capped instead of adding a LIMIT
+    cases = [
+      {"SELECT * FROM users ORDER BY id FETCH FIRST 500 ROWS ONLY", [1], "SELECT * FROM users ORDER BY id FETCH FIRST 10 ROWS ONLY"},
+      {"SELECT * FROM users ORDER BY id FETCH FIRST 5 ROWS WITH TIES", [1], "SELECT * FROM users ORDER BY id FETCH FIRST 5 ROWS ONLY"},
+      {"SELECT * FROM users ORDER BY id FETCH FIRST 5 ROWS ONLY", [0], "SELECT * FROM users ORDER BY id FETCH FIRST 5 ROWS ONLY"},
+    ]
+    for {query, counts, expected} <- cases do
+      assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+      assert {:ok, ^counts} = Parser.apply_rewrites(ref, [{:limit, 10}])
+      assert {:ok, sql} = Parser.to_sql(ref)
+      assert normalize(sql) == normalize(expected)
+    end
+
+    assert {:error, {:invalid_op, {:unknown, 1}}} = Parser.apply_rewrites(ref, [{:unknown, 1}])
+  end
+
+  test "masking columns used in expressions" do
+    mask = {:mask, "users", "ssn", "***"}
+
+    query = "SELECT (ssn), lower(u.ssn) AS l, ssn || '-' || id, (SELECT ssn FROM accounts) FROM users u"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert {:ok, [3]} = Parser.apply_rewrites(ref, [mask])
+    expected = "SELECT '***' AS ssn, lower('***') AS l, '***' || '-' || id, (SELECT ssn FROM accounts) FROM users AS u"
+    assert {:ok, sql} = Parser.to_sql(ref)
+    assert normalize(sql) == normalize(expected)
+
+    for query <- ["SELECT * FROM users", "SELECT u.* FROM users AS u JOIN accounts AS a ON a.id = u.id"] do
+      assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+      assert {:error, {:unsupported_statement, _}} = Parser.apply_rewrites(ref, [mask])
+      assert {:ok, ^query} = Parser.to_sql(ref)
+    end
+
+    # wildcards over other tables don't select the column
+    query = "SELECT a.*, u.id FROM users u JOIN accounts a ON a.id = u.id"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert {:ok, [0]} = Parser.apply_rewrites(ref, [mask])
+  end
+
+  test "composing rewrite rules" do
+    query = "UPDATE users SET name = 'x' WHERE id IN (SELECT user_id FROM orders)"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+
+    ops = [
+      {:filter, "users", "org", :eq, "abc"},
+      {:filter, "orders", "org", :eq, "abc"},
+      {:substitute, "orders", "tenant_1.orders"},
+      {:limit, 10},
+    ]
+    assert {:ok, [1, 1, 1, 0]} = Parser.apply_rewrites(ref, ops)
+    expected = "UPDATE users SET name = 'x' WHERE id IN (SELECT user_id FROM tenant_1.orders AS orders WHERE org = 'abc') AND org = 'abc'"
+    assert {:ok, ^expected} = Parser.to_sql(ref)
+
+    invalid = [
+      {:limit, 1, 2},
+      {:limit, "x"},
+      {"limit", 1},
+      {:filter, "users", "org", :gt, "abc"},
+      {:mask, "users", 1, "***"},
+      :limit,
+    ]
+    for op <- invalid do
+      assert {:error, {:invalid_op, ^op}} = Parser.apply_rewrites(ref, [{:limit, 5}, op])
+    end
+    assert {:ok, ^expected} = Parser.to_sql(ref)
+  end
+
+  test "adding where clause to every relation in a statement" do
+    cases = [
+      {"SELECT id FROM users WHERE EXISTS (SELECT 1 FROM secret WHERE secret.id = users.id)",
+       "SELECT id FROM users WHERE EXISTS (SELECT 1 FROM secret WHERE secret.id = users.id AND org = 'abc')"},
+      {"SELECT id FROM users WHERE id IN (SELECT user_id FROM secret)",
+       "SELECT id FROM users WHERE id IN (SELECT user_id FROM secret WHERE org = 'abc')"},
+      {"SELECT CASE WHEN id > 0 THEN (SELECT name FROM secret) END FROM users",
+       "SELECT CASE WHEN id > 0 THEN (SELECT name FROM secret WHERE org = 'abc') END FROM users"},
+      {"SELECT * FROM users JOIN accounts ON accounts.id IN (SELECT account_id FROM secret)",
+       "SELECT * FROM users JOIN accounts ON accounts.id IN (SELECT account_id FROM secret WHERE org = 'abc')"},
+      {"SELECT * FROM (users JOIN secret ON secret.id = users.id)",
+       "SELECT * FROM (users JOIN secret ON secret.id = users.id) WHERE org = 'abc'"},
+      {"SELECT * FROM users AS u, LATERAL (SELECT * FROM secret AS s WHERE s.user_id = u.id) AS x",
+       "SELECT * FROM users AS u, LATERAL (SELECT * FROM secret AS s WHERE s.user_id = u.id AND org = 'abc') AS x"},
+      {"SELECT * FROM (VALUES (1), ((SELECT id FROM secret))) AS t (id)",
+       "SELECT * FROM (VALUES (1), ((SELECT id FROM secret WHERE org = 'abc'))) AS t (id)"},
+      {"INSERT INTO logs (v) VALUES ((SELECT v FROM secret))",
+       "INSERT INTO logs (v) VALUES ((SELECT v FROM secret WHERE org = 'abc'))"},
+      {"INSERT INTO archive SELECT * FROM secret",
+       "INSERT INTO archive SELECT * FROM secret WHERE org = 'abc'"},
+      {"SELECT SUM(x) OVER (ORDER BY x ROWS BETWEEN (SELECT n FROM secret) PRECEDING AND CURRENT ROW) FROM t",
+       "SELECT SUM(x) OVER (ORDER BY x ROWS BETWEEN (SELECT n FROM secret WHERE org = 'abc') PRECEDING AND CURRENT ROW) FROM t"},
+      {"SELECT org, count(*) FROM users GROUP BY org HAVING count(*) > (SELECT max(n) FROM secret)",
+       "SELECT org, count(*) FROM users GROUP BY org HAVING count(*) > (SELECT max(n) FROM secret WHERE org = 'abc')"},
+      {"SELECT id FROM users ORDER BY (SELECT n FROM secret)",
+       "SELECT id FROM users ORDER BY (SELECT n FROM secret WHERE org = 'abc')"},
+      {"UPDATE users SET name = 'x' FROM secret WHERE secret.id = users.id",
+       "UPDATE users SET name = 'x' FROM secret WHERE secret.id = users.id AND org = 'abc'"},
+      {"UPDATE users SET name = 'x' RETURNING (SELECT n FROM secret)",
+       "UPDATE users SET name = 'x' RETURNING (SELECT n FROM secret WHERE org = 'abc')"},
+      {"DELETE FROM users USING secret WHERE secret.id = users.id",
+       "DELETE FROM users USING secret WHERE secret.id = users.id AND org = 'abc'"},
+      {"WITH s AS (SELECT id FROM secret) SELECT id FROM s",
+       "WITH s AS (SELECT id FROM secret WHERE org = 'abc') SELECT id FROM s"},
+      {"SELECT id FROM secret UNION SELECT id FROM users EXCEPT SELECT user_id FROM secret",
+       "SELECT id FROM secret WHERE org = 'abc' UNION SELECT id FROM users EXCEPT SELECT user_id FROM secret WHERE org = 'abc'"},
+      {"SELECT * FROM secret, UNNEST(secret.tags) AS t (tag)",
+       "SELECT * FROM secret, UNNEST(secret.tags) AS t (tag) WHERE org = 'abc'"},
+      {"SELECT g FROM generate_series(1, (SELECT max(n) FROM secret)) AS g",
+       "SELECT g FROM generate_series(1, (SELECT max(n) FROM secret WHERE org = 'abc')) AS g"},
+      {"SELECT id FROM users UNION TABLE secret",
+       "SELECT id FROM users UNION SELECT * FROM secret WHERE org = 'abc'"},
+      {"INSERT INTO archive TABLE secret",
+       "INSERT INTO archive SELECT * FROM secret WHERE org = 'abc'"},
+    ]
+
+    for {query, expected} <- cases do
+      assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+      assert :ok = Parser.add_table_selection(ref, "secret", "org", :eq, "abc")
+      assert {:ok, sql} = Parser.to_sql(ref)
+      assert normalize(sql) == normalize(expected), query
+    end
+  end
+
+  test "substituting tables and schemas" do
+    query = "SELECT u.id, users.name FROM users AS u JOIN users ON users.id = u.id"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert {:ok, 2} = Parser.substitute_tables(ref, %{"users" => "users_redacted"})
+    expected = "SELECT u.id, users.name FROM users_redacted AS u JOIN users_redacted AS users ON users.id = u.id"
+    assert {:ok, sql} = Parser.to_sql(ref)
+    assert normalize(sql) == normalize(expected)
+
+    query = """
+    WITH recent AS (SELECT * FROM orders WHERE created_at > now())
+    SELECT * FROM recent JOIN users ON users.id = recent.user_id, generate_series(1, 3)
+    """
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert {:ok, 2} = Parser.substitute_tables(ref, [], schema: "tenant_42")
+    expected = """
+    WITH recent AS (SELECT * FROM tenant_42.orders WHERE created_at > now())
+    SELECT * FROM recent JOIN tenant_42.users ON users.id = recent.user_id, generate_series(1, 3)
+    """
+    assert {:ok, sql} = Parser.to_sql(ref)
+    assert normalize(sql) == normalize(expected)
+
+    query = "INSERT INTO users (name) SELECT name FROM public.accounts"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert {:ok, 2} = Parser.substitute_tables(ref, [users: "users_redacted"], schema: "tenant_42")
+    expected = "INSERT INTO tenant_42.users_redacted (name) SELECT name FROM public.accounts"
+    assert {:ok, sql} = Parser.to_sql(ref)
+    assert normalize(sql) == normalize(expected)
+
+    query = "UPDATE users SET name = 'x' WHERE users.id = 1 RETURNING users.id"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert {:ok, 1} = Parser.substitute_tables(ref, users: "tenant_42.users")
+    expected = "UPDATE tenant_42.users AS users SET name = 'x' WHERE users.id = 1 RETURNING users.id"
+    assert {:ok, ^expected} = Parser.to_sql(ref)
+
+    query = "INSERT INTO users (name) VALUES ('x') RETURNING users.id, public.users.name"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert {:ok, 1} = Parser.substitute_tables(ref, users: "tenant_42.users")
+    expected = "INSERT INTO tenant_42.users (name) VALUES ('x') RETURNING tenant_42.users.id, public.users.name"
+    assert {:ok, ^expected} = Parser.to_sql(ref)
+
+    query = "SELECT * FROM pg_type JOIN users ON true JOIN information_schema.columns ON true"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert {:ok, 1} = Parser.substitute_tables(ref, [], schema: "tenant_42")
+    expected = "SELECT * FROM pg_type JOIN tenant_42.users ON true JOIN information_schema.columns ON true"
+    assert {:ok, ^expected} = Parser.to_sql(ref)
+
+    # qualified table names are matched the same way by both NIFs
+    query = "SELECT * FROM public.users JOIN users ON true"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert {:ok, [1]} = Parser.apply_rewrites(ref, [{:substitute, "public.users", "tenant_42.users"}])
+    assert {:ok, 0} = Parser.substitute_tables(ref, %{"public.users" => "tenant_42.users"})
+    expected = "SELECT * FROM tenant_42.users AS users JOIN users ON true"
+    assert {:ok, ^expected} = Parser.to_sql(ref)
+  end
+
+  test "expanding views that read from protected tables" do
+    catalog = Parser.view_catalog()
+    assert :ok = Parser.load_view(catalog, "orders_summary", "SELECT customer_id, sum(total) AS total FROM orders GROUP BY customer_id")
+    assert :ok = Parser.load_view(catalog, "big_customers", "CREATE VIEW big_customers (id) AS SELECT customer_id FROM orders_summary WHERE total > 100")
+    assert :ok = Parser.load_view(catalog, "customer_names", "SELECT id, name FROM customers")
+    assert :ok = Parser.load_view(catalog, "reports.order_totals", "SELECT sum(total) AS total FROM public.orders")
+    assert {:error, {:invalid_view, _}} = Parser.load_view(catalog, "dropped", "DROP VIEW dropped")
+    assert {:error, {:parser_error, _}} = Parser.load_view(catalog, "broken", "SELECT FROM WHERE (")
+
+    query = "SELECT s.total, c.name FROM orders_summary s JOIN customer_names c ON c.id = s.customer_id WHERE s.customer_id IN (SELECT id FROM big_customers)"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    ops = [{:expand_views, catalog, ["orders"]}, {:filter, "orders", "org", :eq, "abc"}]
+    assert {:ok, [3, 2]} = Parser.apply_rewrites(ref, ops)
+    expected = """
+    SELECT s.total, c.name
+    FROM (SELECT customer_id, sum(total) AS total FROM orders WHERE org = 'abc' GROUP BY customer_id) AS s
+    JOIN customer_names AS c ON c.id = s.customer_id
+    WHERE s.customer_id IN (
+      SELECT id FROM (
+        SELECT customer_id FROM (SELECT customer_id, sum(total) AS total FROM orders WHERE org = 'abc' GROUP BY customer_id) AS orders_summary
+        WHERE total > 100
+      ) AS big_customers (id)
+    )
+    """
+    assert {:ok, sql} = Parser.to_sql(ref)
+    assert normalize(sql) == normalize(expected)
+
+    # only the view being written to is left in place, not reads of it
+    query = "UPDATE orders_summary SET total = 0 FROM orders_summary AS o WHERE o.customer_id = orders_summary.customer_id"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert {:ok, 1} = Parser.expand_views(ref, catalog, ["orders"])
+    expected = """
+    UPDATE orders_summary SET total = 0
+    FROM (SELECT customer_id, sum(total) AS total FROM orders GROUP BY customer_id) AS o
+    WHERE o.customer_id = orders_summary.customer_id
+    """
+    assert {:ok, sql} = Parser.to_sql(ref)
+    assert normalize(sql) == normalize(expected)
+
+    query = "DELETE FROM orders_summary WHERE customer_id IN (SELECT customer_id FROM orders_summary)"
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql(query)
+    assert {:ok, 1} = Parser.expand_views(ref, catalog, ["orders"])
+
+    # names without a schema are in public
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql("SELECT * FROM reports.order_totals")
+    assert {:ok, 1} = Parser.expand_views(ref, catalog, ["orders"])
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql("SELECT * FROM order_totals")
+    assert {:ok, 0} = Parser.expand_views(ref, catalog, ["orders"])
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql("SELECT * FROM customer_names, orders_summary")
+    assert {:ok, 1} = Parser.expand_views(ref, catalog, ["public.orders"])
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql("SELECT * FROM orders_summary, reports.order_totals")
+    assert {:ok, 0} = Parser.expand_views(ref, catalog, ["reports.orders"])
+    assert :ok = Parser.remove_view(catalog, "orders_summary")
+    assert {:ok, [{_, ref}]} = Parser.parse_postgresql("SELECT * FROM orders_summary")
+    assert {:ok, 0} = Parser.expand_views(ref, catalog, ["orders"])
+  end
+
   test "parse TRUNCATE statements" do
     query = "TRUNCATE mytable;"
     assert {:ok, [statement]} = parse_query(query)